@@ -0,0 +1,192 @@
+use crate::{components::*, resources::load_json_or_default};
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashMap, path::PathBuf};
+
+/// Below this speed, in pixels per second, an entity counts as standing still.
+const ANIMATION_MOVE_THRESHOLD: f32 = 5.;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash, Serialize, Deserialize)]
+pub enum AnimationState {
+    #[default]
+    Idle,
+    Run,
+    Jump,
+    Fall,
+    Climb,
+}
+
+/// A range of frames in an entity's texture atlas.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationClip {
+    pub first: usize,
+    pub last: usize,
+    pub fps: f32,
+    /// Clips that don't loop stay on their last frame.
+    #[serde(default = "default_looping")]
+    pub looping: bool,
+}
+
+fn default_looping() -> bool {
+    true
+}
+
+impl AnimationClip {
+    pub fn frame_count(&self) -> usize {
+        self.last.saturating_sub(self.first) + 1
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnimationSet {
+    /// States without a clip use the `Idle` clip.
+    pub clips: HashMap<AnimationState, AnimationClip>,
+    /// Whether the frames are drawn facing left, so they're flipped when moving right instead.
+    pub faces_left: bool,
+}
+
+impl AnimationSet {
+    pub fn clip(&self, state: AnimationState) -> Option<&AnimationClip> {
+        self.clips
+            .get(&state)
+            .or_else(|| self.clips.get(&AnimationState::Idle))
+    }
+}
+
+/// Animation sets keyed by the identifier of the LDtk entity they animate.
+///
+/// Loaded from `assets/animations.json` on startup.
+/// Frames are indices into the entity's texture atlas,
+/// which is the LDtk tileset for entities using `#[sprite_sheet_bundle]` without arguments.
+#[derive(Clone, Debug, Default, PartialEq, Resource, Serialize, Deserialize)]
+pub struct Animations(pub HashMap<String, AnimationSet>);
+
+impl Animations {
+    pub fn path() -> PathBuf {
+        FileAssetIo::get_base_path()
+            .join("assets")
+            .join("animations.json")
+    }
+
+    /// Loads the animations file, falling back to no animations if it's missing or invalid.
+    pub fn load_or_default() -> Self {
+        load_json_or_default(&Self::path(), "animations")
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct Animator {
+    /// Key of the entity's set in `Animations`
+    pub set: String,
+    pub state: AnimationState,
+    /// Index into the current clip
+    pub frame: usize,
+    pub timer: f32,
+}
+
+impl Animator {
+    pub fn set_state(&mut self, state: AnimationState) {
+        if self.state != state {
+            self.state = state;
+            self.frame = 0;
+            self.timer = 0.;
+        }
+    }
+}
+
+impl From<EntityInstance> for Animator {
+    fn from(entity_instance: EntityInstance) -> Self {
+        Animator {
+            set: entity_instance.identifier,
+            ..Default::default()
+        }
+    }
+}
+
+/// Picks the animation state from how an entity is moving.
+///
+/// Entities without `GroundDetection`, like mobs, are always treated as grounded.
+#[allow(clippy::type_complexity)]
+pub fn select_animation_state(
+    mut query: Query<(
+        &mut Animator,
+        &Velocity,
+        Option<&GroundDetection>,
+        Option<&Climber>,
+    )>,
+) {
+    for (mut animator, velocity, ground_detection, climber) in &mut query {
+        let on_ground = ground_detection
+            .map(|ground_detection| ground_detection.on_ground)
+            .unwrap_or(true);
+
+        let state = if matches!(climber, Some(climber) if climber.climbing) {
+            AnimationState::Climb
+        } else if on_ground {
+            if velocity.linvel.x.abs() > ANIMATION_MOVE_THRESHOLD {
+                AnimationState::Run
+            } else {
+                AnimationState::Idle
+            }
+        } else if velocity.linvel.y > 0. {
+            AnimationState::Jump
+        } else {
+            AnimationState::Fall
+        };
+
+        if animator.state != state {
+            animator.set_state(state);
+        }
+    }
+}
+
+/// Advances the frames of animated sprites and flips them to face the way they're moving.
+pub fn animate_sprites(
+    time: Res<Time>,
+    animations: Res<Animations>,
+    mut query: Query<(&mut Animator, &mut TextureAtlasSprite, &Velocity)>,
+) {
+    for (mut animator, mut sprite, velocity) in &mut query {
+        let set = match animations.0.get(&animator.set) {
+            Some(set) => set,
+            None => continue,
+        };
+        let clip = match set.clip(animator.state) {
+            Some(clip) => *clip,
+            None => continue,
+        };
+
+        // Hanging still on a ladder pauses the climbing animation
+        let paused = animator.state == AnimationState::Climb
+            && velocity.linvel.y.abs() <= ANIMATION_MOVE_THRESHOLD;
+
+        if !paused && clip.fps > 0. {
+            animator.timer += time.delta_seconds();
+            let frame_time = 1. / clip.fps;
+            while animator.timer >= frame_time {
+                animator.timer -= frame_time;
+                animator.frame = if clip.looping {
+                    (animator.frame + 1) % clip.frame_count()
+                } else {
+                    (animator.frame + 1).min(clip.frame_count() - 1)
+                };
+            }
+        }
+
+        let index = clip.first + animator.frame.min(clip.frame_count() - 1);
+        if sprite.index != index {
+            sprite.index = index;
+        }
+
+        if velocity.linvel.x.abs() > ANIMATION_MOVE_THRESHOLD {
+            let flip_x = (velocity.linvel.x < 0.) != set.faces_left;
+            if sprite.flip_x != flip_x {
+                sprite.flip_x = flip_x;
+            }
+        }
+    }
+}
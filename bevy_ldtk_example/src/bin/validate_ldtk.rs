@@ -0,0 +1,54 @@
+//! Reports problems in LDtk projects that the game would skip with an error at runtime.
+//!
+//! Usage: `cargo run --bin validate_ldtk -- [PROJECT.ldtk]...`
+//!
+//! Without arguments, every project in `assets` is checked.
+//! Exits with status 1 if any problems were found, and 2 if a project couldn't be read.
+
+use bevy_ldtk_example::validation::{validate_project_file, ValidationRules};
+
+use std::{env, ffi::OsStr, fs, path::PathBuf, process::ExitCode};
+
+fn main() -> ExitCode {
+    let mut paths: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();
+
+    if paths.is_empty() {
+        let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+        if let Ok(entries) = fs::read_dir(&assets) {
+            paths = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension() == Some(OsStr::new("ldtk")))
+                .collect();
+            paths.sort();
+        }
+    }
+
+    let rules = ValidationRules::game();
+    let mut found_problems = false;
+    let mut unreadable = false;
+
+    for path in paths {
+        match validate_project_file(&path, &rules) {
+            Ok(errors) if errors.is_empty() => println!("{}: ok", path.display()),
+            Ok(errors) => {
+                println!("{}: {} problem(s)", path.display(), errors.len());
+                for error in errors {
+                    println!("  {error}");
+                }
+                found_problems = true;
+            }
+            Err(e) => {
+                eprintln!("{}: couldn't read project: {e}", path.display());
+                unreadable = true;
+            }
+        }
+    }
+
+    if unreadable {
+        ExitCode::from(2)
+    } else if found_problems {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
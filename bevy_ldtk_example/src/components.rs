@@ -1,3 +1,5 @@
+use crate::animation::Animator;
+use crate::ldtk_fields::{impl_ldtk_enum_field, impl_ldtk_fields, EntityInstanceFields};
 use bevy::prelude::*;
 use bevy_ecs_ldtk::{prelude::*, utils::ldtk_pixel_coords_to_translation_pivoted};
 
@@ -14,6 +16,8 @@ pub struct ColliderBundle {
     pub gravity_scale: GravityScale,
     pub friction: Friction,
     pub density: ColliderMassProperties,
+    /// Read by pressure plates
+    pub mass_properties: ReadMassProperties,
 }
 
 #[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
@@ -24,6 +28,22 @@ pub struct SensorBundle {
     pub rotation_constraints: LockedAxes,
 }
 
+/// Entities drawn as rectangles in LDtk, like platforms, doors and switches,
+/// are drawn the same way in game.
+pub fn rectangle_sprite(entity_instance: &EntityInstance) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite {
+            color: entity_instance.smart_color,
+            custom_size: Some(Vec2::new(
+                entity_instance.width as f32,
+                entity_instance.height as f32,
+            )),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
 impl From<EntityInstance> for ColliderBundle {
     fn from(entity_instance: EntityInstance) -> ColliderBundle {
         let rotation_constraints = LockedAxes::ROTATION_LOCKED;
@@ -54,54 +74,69 @@ impl From<EntityInstance> for ColliderBundle {
                 density: ColliderMassProperties::Density(15.0),
                 ..Default::default()
             },
-            _ => ColliderBundle::default(),
-        }
-    }
-}
-
-impl From<IntGridCell> for SensorBundle {
-    fn from(int_grid_cell: IntGridCell) -> SensorBundle {
-        let rotation_constraints = LockedAxes::ROTATION_LOCKED;
-
-        // ladder
-        if int_grid_cell.value == 2 {
-            SensorBundle {
-                collider: Collider::cuboid(8., 8.),
-                sensor: Sensor,
+            "MovingPlatform" => ColliderBundle {
+                collider: Collider::cuboid(
+                    entity_instance.width as f32 / 2.,
+                    entity_instance.height as f32 / 2.,
+                ),
+                rigid_body: RigidBody::KinematicVelocityBased,
                 rotation_constraints,
-                active_events: ActiveEvents::COLLISION_EVENTS,
                 ..Default::default()
-            }
-        } else {
-            SensorBundle::default()
+            },
+            "Gate" => ColliderBundle {
+                collider: Collider::cuboid(
+                    entity_instance.width as f32 / 2.,
+                    entity_instance.height as f32 / 2.,
+                ),
+                rigid_body: RigidBody::Fixed,
+                rotation_constraints,
+                ..Default::default()
+            },
+            _ => ColliderBundle::default(),
         }
     }
 }
 
+/// Item ids, matching the values of the `Item` enum in LDtk.
 #[derive(Clone, Component, Debug, Eq, Default, PartialEq)]
 pub struct Items(Vec<String>);
 
-impl From<EntityInstance> for Items {
-    fn from(entity_instance: EntityInstance) -> Self {
-        let mut items: Vec<String> = vec![];
+impl Items {
+    pub fn new(items: Vec<String>) -> Self {
+        Items(items)
+    }
 
-        if let Some(field_instance) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == *"items")
-        {
-            // convert &String to String which returns vec![String::from("Knife"), String::from("Boot")]
-            items = match &field_instance.value {
-                FieldValue::Enums(v) => v
-                    .iter()
-                    .flatten()
-                    .map(|s| s.into())
-                    .collect::<Vec<String>>(),
-                _ => vec![],
-            };
-        }
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// How many of an item there are.
+    pub fn count(&self, id: &str) -> usize {
+        self.0.iter().filter(|item| *item == id).count()
+    }
+
+    /// Moves all items out of `other` into `self`.
+    pub fn take_all(&mut self, other: &mut Items) {
+        self.0.append(&mut other.0);
+    }
+}
+
+// The player lists its items in `items`, chests in `content`
+impl_ldtk_fields!(Items {
+    0: "items" | "content",
+});
 
-        Self(items)
+/// The iid of an LDtk entity, used to remember changes to it across level respawns and saves.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Component)]
+pub struct EntityIid(pub String);
+
+impl From<EntityInstance> for EntityIid {
+    fn from(entity_instance: EntityInstance) -> Self {
+        EntityIid(entity_instance.iid)
     }
 }
 
@@ -116,9 +151,9 @@ pub struct Climber {
 
 #[derive(Clone, Default, Bundle, LdtkEntity)]
 pub struct PlayerBundle {
-    #[sprite_bundle("player.png")]
+    #[sprite_sheet_bundle("player_sheet.png", 32., 32., 10, 1, 0., 0., 0)]
     #[bundle]
-    pub sprite_bundle: SpriteBundle,
+    pub sprite_sheet_bundle: SpriteSheetBundle,
     #[from_entity_instance]
     #[bundle]
     pub collider_bundle: ColliderBundle,
@@ -126,17 +161,35 @@ pub struct PlayerBundle {
     #[worldly]
     pub worldly: Worldly,
     pub climber: Climber,
+    pub collision_groups: CollisionGroups,
     pub ground_detection: GroundDetection,
+    pub wall_detection: WallDetection,
+    pub movement_state: MovementState,
+    pub health: Health,
+    pub swimmer: Swimmer,
+    #[from_entity_instance]
+    pub animator: Animator,
 
-    // Build Items Component manually by using `impl From<EntityInstance>
+    // Build Items Component from its LDtk field with `impl_ldtk_fields!`
     #[from_entity_instance]
     items: Items,
 
     // The whole EntityInstance can be stored directly as an EntityInstance component
     #[from_entity_instance]
     entity_instance: EntityInstance,
+    // So invalid players are skipped like other entities
+    #[from_entity_instance]
+    pub entity_iid: EntityIid,
 }
 
+// Values of the IntGrid layer, named after their identifiers in LDtk
+pub const DIRT: i32 = 1;
+pub const LADDER: i32 = 2;
+pub const STONE: i32 = 3;
+pub const SPIKES: i32 = 4;
+pub const WATER: i32 = 5;
+pub const LAVA: i32 = 6;
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Wall;
 
@@ -145,27 +198,283 @@ pub struct WallBundle {
     wall: Wall,
 }
 
+/// Marks a merged collider spawned by `spawn_wall_collision`,
+/// so it can be replaced when the walls of its level change.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct WallCollider;
+
+/// Marks a level entity whose merged wall, ladder and terrain volume colliders are out of date.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct RebuildWallCollision;
+
+/// Changes a single IntGrid cell at runtime, e.g. for bombs, crumbling blocks or building.
+///
+/// Positions are in world space, the affected level is the one containing the position.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TerrainEdit {
+    Add { position: Vec2, value: i32 },
+    Remove { position: Vec2 },
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Climbable;
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Ladder;
+
 #[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
 pub struct LadderBundle {
+    ladder: Ladder,
+}
+
+/// A climbable sensor covering a column of ladder tiles, spawned by `spawn_ladder_collision`.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct LadderColumn {
+    /// Height of the top of the highest tile above the center of the column
+    pub top: f32,
+}
+
+/// The solid top of a ladder column, which climbers pass through while climbing.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LadderTop;
+
+/// Collision group of `LadderTop`s, removed from a climber's filters to pass through them.
+pub const LADDER_TOP_GROUP: Group = Group::GROUP_2;
+
+/// IntGrid cells that hurt the player, like spikes and lava.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Hazard {
+    /// Kills instead of dealing damage
+    pub lethal: bool,
+}
+
+impl From<IntGridCell> for Hazard {
+    fn from(int_grid_cell: IntGridCell) -> Hazard {
+        Hazard {
+            lethal: int_grid_cell.value == LAVA,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum LiquidKind {
+    #[default]
+    Water,
+    Lava,
+}
+
+/// IntGrid cells bodies can swim or sink in, tuned by `LiquidConfig`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Liquid {
+    pub kind: LiquidKind,
+}
+
+impl From<IntGridCell> for Liquid {
+    fn from(int_grid_cell: IntGridCell) -> Liquid {
+        let kind = match int_grid_cell.value {
+            LAVA => LiquidKind::Lava,
+            _ => LiquidKind::Water,
+        };
+
+        Liquid { kind }
+    }
+}
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct HazardBundle {
     #[from_int_grid_cell]
-    #[bundle]
-    pub sensor_bundle: SensorBundle,
-    pub climbable: Climbable,
+    hazard: Hazard,
+}
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct LiquidBundle {
+    #[from_int_grid_cell]
+    liquid: Liquid,
+}
+
+/// Lava is a liquid that kills.
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct LavaBundle {
+    #[from_int_grid_cell]
+    hazard: Hazard,
+    #[from_int_grid_cell]
+    liquid: Liquid,
+}
+
+/// A sensor covering a rectangle of hazard or liquid cells with the same IntGrid value,
+/// spawned by `spawn_terrain_volumes` with the `Hazard` and `Liquid` of its cells.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct TerrainVolume;
+
+/// A body affected by liquids, kept up to date by `detect_liquids`.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct Swimmer {
+    /// How much of the body is below the surface, from 0 to 1
+    pub submerged: f32,
+    /// The liquid the body is deepest in
+    pub liquid: Option<LiquidKind>,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Enemy;
 
-#[derive(Clone, PartialEq, Debug, Default, Component)]
+/// What a patrol does after reaching its last point.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum PatrolMode {
+    /// Walk back through the points in reverse
+    #[default]
+    PingPong,
+    /// Walk from the last point straight back to the first one
+    Loop,
+    /// Stop at the last point
+    Once,
+}
+
+impl_ldtk_enum_field!(PatrolMode {
+    PingPong,
+    Loop,
+    Once,
+});
+
+#[derive(Clone, PartialEq, Debug, Component)]
 pub struct Patrol {
+    /// The spawn point followed by the points of the `patrol` field
     pub points: Vec<Vec2>,
+    /// The point the entity is heading to
     pub index: usize,
     pub forward: bool,
+    pub mode: PatrolMode,
+    /// In pixels per second
+    pub speed: f32,
+    /// Seconds to wait at each point, indexed like `points`, so the first entry is for the
+    /// spawn point. Points without an entry aren't waited at.
+    pub waits: Vec<f32>,
+    /// Within this many pixels of a point the entity slows down, 0 to keep full speed
+    pub ease_distance: f32,
+    /// Remaining time to wait at the current point
+    pub wait_timer: f32,
+    /// Set once a `PatrolMode::Once` patrol reaches its last point
+    pub finished: bool,
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Patrol {
+            points: Vec::new(),
+            index: 1,
+            forward: true,
+            mode: PatrolMode::PingPong,
+            speed: 75.,
+            waits: Vec::new(),
+            ease_distance: 0.,
+            wait_timer: 0.,
+            finished: false,
+        }
+    }
+}
+
+impl_ldtk_fields!(Patrol {
+    mode: "patrol_mode",
+    speed: "patrol_speed",
+    // One wait per point, starting with the spawn point before the `patrol` points
+    waits: "patrol_waits",
+    ease_distance: "patrol_ease_distance",
+});
+
+impl Patrol {
+    pub fn nearest_point(&self, position: Vec2) -> usize {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.distance(position).total_cmp(&b.distance(position)))
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+
+    /// Continues the patrol towards the point after `index`, for an entity standing on it.
+    pub fn resume_from(&mut self, index: usize) {
+        if self.points.len() <= 1 {
+            return;
+        }
+
+        let last = self.points.len() - 1;
+        match self.mode {
+            PatrolMode::PingPong => {
+                if index == 0 {
+                    self.forward = true;
+                } else if index == last {
+                    self.forward = false;
+                }
+
+                self.index = if self.forward { index + 1 } else { index - 1 };
+            }
+            PatrolMode::Loop => self.index = (index + 1) % self.points.len(),
+            PatrolMode::Once => {
+                self.index = (index + 1).min(last);
+                self.finished = index == last;
+            }
+        }
+    }
+
+    /// How long to wait at a point, in seconds.
+    pub fn wait_at(&self, index: usize) -> f32 {
+        self.waits.get(index).copied().unwrap_or(0.)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum EnemyState {
+    #[default]
+    Patrol,
+    /// Spotted the player, waiting for `alert_time` before chasing
+    Alert,
+    Chase,
+    /// Lost the player, heading back to the closest patrol point
+    Return,
 }
 
+/// Drives `Enemy` behaviour, configured by the fields of the `Mob` entity in LDtk.
+///
+/// Distances are in pixels, speeds in pixels per second and times in seconds.
+#[derive(Clone, PartialEq, Debug, Component)]
+pub struct EnemyAi {
+    pub state: EnemyState,
+    /// Counts down the alert time while alert, and the give up time while chasing
+    pub timer: f32,
+    pub last_seen_player: Vec2,
+    /// How far away the player can be spotted from
+    pub sight_range: f32,
+    /// How far away a spotted player is still chased
+    pub chase_range: f32,
+    pub chase_speed: f32,
+    pub alert_time: f32,
+    /// How long the player can be out of sight or range before giving up the chase
+    pub give_up_time: f32,
+}
+
+impl Default for EnemyAi {
+    fn default() -> Self {
+        EnemyAi {
+            state: EnemyState::Patrol,
+            timer: 0.,
+            last_seen_player: Vec2::ZERO,
+            sight_range: 96.,
+            chase_range: 160.,
+            chase_speed: 100.,
+            alert_time: 0.4,
+            give_up_time: 1.5,
+        }
+    }
+}
+
+impl_ldtk_fields!(EnemyAi {
+    sight_range: "sight_range",
+    chase_range: "chase_range",
+    chase_speed: "chase_speed",
+    alert_time: "alert_time",
+    give_up_time: "give_up_time",
+});
+
 impl LdtkEntity for Patrol {
     fn bundle_entity(
         entity_instance: &EntityInstance,
@@ -183,36 +492,36 @@ impl LdtkEntity for Patrol {
             entity_instance.pivot,
         ));
 
-        let ldtk_patrol = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == *"patrol")
-            .unwrap();
-        if let FieldValue::Points(ldtk_points) = &ldtk_patrol.value {
-            for ldtk_point in ldtk_points {
-                if let Some(ldtk_point) = ldtk_point {
-                    // The +1 is necessary here due to the pivot of the entities in the sample
-                    // file.
-                    // The patrols set up in the file look flat and grounded,
-                    // but technically they're not if you consider the pivot,
-                    // which is at the bottom-center for the skulls.
-                    let pixel_coords = (ldtk_point.as_vec2() + Vec2::new(0.5, 1.))
-                        * Vec2::splat(layer_instance.grid_size as f32);
-
-                    points.push(ldtk_pixel_coords_to_translation_pivoted(
-                        pixel_coords.as_ivec2(),
-                        layer_instance.c_hei * layer_instance.grid_size,
-                        IVec2::new(entity_instance.width, entity_instance.height),
-                        entity_instance.pivot,
-                    ));
-                }
+        let ldtk_points: Vec<IVec2> = match entity_instance.required_field("patrol") {
+            Ok(ldtk_points) => ldtk_points,
+            Err(e) => {
+                warn!(
+                    "Invalid patrol for {} {}: {e}",
+                    entity_instance.identifier, entity_instance.iid
+                );
+                Vec::new()
             }
+        };
+
+        for ldtk_point in ldtk_points {
+            // Points are placed so the entity's pivot ends up at the same spot of the cell.
+            // The patrols set up in the file look flat and grounded,
+            // but technically they're not if you consider the pivot,
+            // which is at the bottom-center for the skulls.
+            let pixel_coords = (ldtk_point.as_vec2() + entity_instance.pivot)
+                * Vec2::splat(layer_instance.grid_size as f32);
+
+            points.push(ldtk_pixel_coords_to_translation_pivoted(
+                pixel_coords.as_ivec2(),
+                layer_instance.c_hei * layer_instance.grid_size,
+                IVec2::new(entity_instance.width, entity_instance.height),
+                entity_instance.pivot,
+            ));
         }
 
         Patrol {
             points,
-            index: 1,
-            forward: true,
+            ..Patrol::from(entity_instance.clone())
         }
     }
 }
@@ -228,8 +537,17 @@ pub struct MobBundle {
     pub enemy: Enemy,
     #[ldtk_entity]
     pub patrol: Patrol,
+    #[from_entity_instance]
+    pub animator: Animator,
+    #[from_entity_instance]
+    pub enemy_ai: EnemyAi,
+    #[from_entity_instance]
+    pub entity_iid: EntityIid,
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Chest;
+
 #[derive(Clone, Default, Bundle, LdtkEntity)]
 pub struct ChestBundle {
     #[sprite_sheet_bundle]
@@ -238,11 +556,20 @@ pub struct ChestBundle {
     #[from_entity_instance]
     #[bundle]
     pub collider_bundle: ColliderBundle,
+    pub chest: Chest,
+    pub ground_detection: GroundDetection,
+    pub swimmer: Swimmer,
+    #[from_entity_instance]
+    pub items: Items,
+    #[from_entity_instance]
+    pub entity_iid: EntityIid,
 }
 
 #[derive(Clone, Default, Component)]
 pub struct GroundDetection {
     pub on_ground: bool,
+    /// Velocity of the moving platform being stood on, kept up to date by `ride_platforms`
+    pub ground_velocity: Vec2,
 }
 
 #[derive(Component)]
@@ -250,3 +577,84 @@ pub struct GroundSensor {
     pub ground_detection_entity: Entity,
     pub intersecting_ground_entities: HashSet<Entity>,
 }
+
+#[derive(Clone, Default, Component)]
+pub struct WallDetection {
+    pub on_wall_left: bool,
+    pub on_wall_right: bool,
+}
+
+#[derive(Component)]
+pub struct WallSensor {
+    pub wall_detection_entity: Entity,
+    /// -1 for the sensor on the left side, 1 for the right side
+    pub side: f32,
+    pub intersecting_wall_entities: HashSet<Entity>,
+}
+
+/// Timers and flags `movement` keeps between frames.
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct MovementState {
+    pub time_since_grounded: f32,
+    pub jump_buffer: f32,
+    /// Set while rising from a jump that can still be cut short.
+    pub jumping: bool,
+    /// Horizontal input is ignored while this is above 0, after wall jumps and knockback.
+    pub control_lock: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Component)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+    /// Remaining time in seconds during which damage is ignored.
+    pub invincible_for: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            current: 3,
+            max: 3,
+            invincible_for: 0.,
+        }
+    }
+}
+
+/// Inserted on the player when its health runs out, it's respawned when the timer finishes.
+#[derive(Clone, Debug, Component)]
+pub struct Dying {
+    pub timer: Timer,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Checkpoint;
+
+#[derive(Clone, Default, Bundle, LdtkEntity)]
+pub struct CheckpointBundle {
+    #[sprite_sheet_bundle]
+    #[bundle]
+    pub sprite_sheet_bundle: SpriteSheetBundle,
+    pub checkpoint: Checkpoint,
+    #[from_entity_instance]
+    pub entity_iid: EntityIid,
+}
+
+/// State of the camera following the player, tuned by `CameraConfig`.
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct CameraController {
+    /// The point the camera is centered on before look ahead, trails the player by the dead zone
+    pub focus: Vec2,
+    pub look_ahead: f32,
+    /// The level the camera was last fit inside
+    pub level_iid: Option<String>,
+    pub transition: Option<CameraTransition>,
+}
+
+/// An eased move from the view of the previous level to the view of the current one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraTransition {
+    pub from_center: Vec2,
+    pub from_size: Vec2,
+    pub elapsed: f32,
+}
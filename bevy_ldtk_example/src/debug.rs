@@ -0,0 +1,382 @@
+//! A debug overlay drawing the colliders and sensors the platformer relies on,
+//! with an inspector panel for the entity clicked while it's open.
+//!
+//! Toggled with the debug action, `P` by default.
+
+use crate::{components::*, input::*};
+use bevy::{prelude::*, utils::get_short_name};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+/// Thickness of the lines of the overlay, in pixels.
+const LINE_WIDTH: f32 = 1.;
+
+/// In front of everything in the levels, but behind the camera.
+const OVERLAY_Z: f32 = 900.;
+
+const WALL_COLOR: Color = Color::rgba(1., 0.2, 0.2, 0.8);
+const LADDER_COLOR: Color = Color::rgba(1., 0.85, 0., 0.8);
+const GROUND_SENSOR_COLOR: Color = Color::rgba(0.6, 0.6, 0.6, 0.8);
+/// Ground sensors touching the ground, and the entities they touch.
+const GROUNDED_COLOR: Color = Color::rgba(0.2, 1., 0.2, 0.9);
+/// Climbables a climber is in range of.
+const CLIMBABLE_COLOR: Color = Color::rgba(0.2, 0.9, 1., 0.9);
+const PATROL_COLOR: Color = Color::rgba(1., 0.3, 1., 0.8);
+const SELECTED_COLOR: Color = Color::WHITE;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Resource)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+    /// The entity shown in the inspector panel, picked by clicking on it
+    pub selected: Option<Entity>,
+}
+
+/// Marks the entity the shapes of the overlay are drawn under.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DebugShapes;
+
+/// Marks the UI node of the inspector panel.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DebugPanel;
+
+fn panel_style(display: Display) -> Style {
+    Style {
+        display,
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            left: Val::Px(8.),
+            bottom: Val::Px(8.),
+            ..Default::default()
+        },
+        max_size: Size::new(Val::Percent(30.), Val::Percent(60.)),
+        flex_direction: FlexDirection::Column,
+        padding: UiRect::all(Val::Px(4.)),
+        ..Default::default()
+    }
+}
+
+pub fn setup_debug_overlay(mut commands: Commands) {
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_xyz(0., 0., OVERLAY_Z)),
+        DebugShapes,
+    ));
+
+    commands.spawn((
+        NodeBundle {
+            style: panel_style(Display::None),
+            background_color: Color::rgba(0., 0., 0., 0.7).into(),
+            ..Default::default()
+        },
+        DebugPanel,
+    ));
+}
+
+pub fn toggle_debug_overlay(actions: Res<ActionState>, mut overlay: ResMut<DebugOverlay>) {
+    if actions.just_pressed(Action::Debug) {
+        overlay.enabled = !overlay.enabled;
+        overlay.selected = None;
+    }
+}
+
+/// Selects the smallest LDtk entity under the cursor when clicking while the overlay is open.
+pub fn select_debug_entity(
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    entities: Query<(Entity, &GlobalTransform, &EntityInstance)>,
+    mut overlay: ResMut<DebugOverlay>,
+) {
+    if !overlay.enabled || !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let cursor_position = windows.get_primary().and_then(Window::cursor_position);
+
+    for (camera, camera_transform) in &camera_query {
+        let position = match cursor_position
+            .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        {
+            Some(ray) => ray.origin.truncate(),
+            None => continue,
+        };
+
+        // Entity translations are at their center, whatever their pivot
+        overlay.selected = entities
+            .iter()
+            .filter(|(_, transform, entity_instance)| {
+                let offset = position - transform.translation().truncate();
+                offset.x.abs() <= entity_instance.width as f32 / 2.
+                    && offset.y.abs() <= entity_instance.height as f32 / 2.
+            })
+            .min_by_key(|(_, _, entity_instance)| entity_instance.width * entity_instance.height)
+            .map(|(entity, _, _)| entity);
+    }
+}
+
+/// A line of the overlay, in world space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Line {
+    from: Vec2,
+    to: Vec2,
+    color: Color,
+}
+
+fn spawn_line(shapes: &mut ChildBuilder, line: &Line) {
+    let offset = line.to - line.from;
+    shapes.spawn(SpriteBundle {
+        sprite: Sprite {
+            color: line.color,
+            custom_size: Some(Vec2::new(offset.length() + LINE_WIDTH, LINE_WIDTH)),
+            ..Default::default()
+        },
+        transform: Transform::from_translation(((line.from + line.to) / 2.).extend(0.))
+            .with_rotation(Quat::from_rotation_z(offset.y.atan2(offset.x))),
+        ..Default::default()
+    });
+}
+
+fn push_rect(lines: &mut Vec<Line>, center: Vec2, half_size: Vec2, color: Color) {
+    let min = center - half_size;
+    let max = center + half_size;
+    let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+    for i in 0..corners.len() {
+        lines.push(Line {
+            from: corners[i],
+            to: corners[(i + 1) % corners.len()],
+            color,
+        });
+    }
+}
+
+fn push_collider(
+    lines: &mut Vec<Line>,
+    transform: &GlobalTransform,
+    collider: &Collider,
+    color: Color,
+) {
+    if let Some(cuboid) = collider.as_cuboid() {
+        push_rect(
+            lines,
+            transform.translation().truncate(),
+            cuboid.half_extents(),
+            color,
+        );
+    }
+}
+
+/// Rebuilds the overlay's sprites when the lines it shows change.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn draw_debug_overlay(
+    mut commands: Commands,
+    overlay: Res<DebugOverlay>,
+    shapes_query: Query<Entity, With<DebugShapes>>,
+    colliders: Query<(&GlobalTransform, &Collider)>,
+    walls: Query<Entity, With<WallCollider>>,
+    ladders: Query<Entity, Or<(With<LadderColumn>, With<LadderTop>)>>,
+    ground_sensors: Query<(Entity, &GroundSensor)>,
+    climbers: Query<&Climber>,
+    patrols: Query<(&Patrol, &Parent)>,
+    transforms: Query<&GlobalTransform>,
+    entity_instances: Query<(&GlobalTransform, &EntityInstance)>,
+    mut shown: Local<Vec<Line>>,
+) {
+    let mut lines = Vec::new();
+
+    if overlay.enabled {
+        let draw_collider = |lines: &mut Vec<Line>, entity: Entity, color: Color| {
+            if let Ok((transform, collider)) = colliders.get(entity) {
+                push_collider(lines, transform, collider, color);
+            }
+        };
+
+        for wall in &walls {
+            draw_collider(&mut lines, wall, WALL_COLOR);
+        }
+
+        for ladder in &ladders {
+            draw_collider(&mut lines, ladder, LADDER_COLOR);
+        }
+
+        for climber in &climbers {
+            for climbable in &climber.intersecting_climbables {
+                draw_collider(&mut lines, *climbable, CLIMBABLE_COLOR);
+            }
+        }
+
+        for (sensor, ground_sensor) in &ground_sensors {
+            if ground_sensor.intersecting_ground_entities.is_empty() {
+                draw_collider(&mut lines, sensor, GROUND_SENSOR_COLOR);
+            } else {
+                draw_collider(&mut lines, sensor, GROUNDED_COLOR);
+                for ground in &ground_sensor.intersecting_ground_entities {
+                    draw_collider(&mut lines, *ground, GROUNDED_COLOR);
+                }
+            }
+        }
+
+        // Patrol points are relative to the entity's layer
+        for (patrol, parent) in &patrols {
+            let layer_transform = match transforms.get(parent.get()) {
+                Ok(transform) => transform,
+                Err(_) => continue,
+            };
+            let points: Vec<Vec2> = patrol
+                .points
+                .iter()
+                .map(|point| layer_transform.transform_point(point.extend(0.)).truncate())
+                .collect();
+
+            for pair in points.windows(2) {
+                lines.push(Line {
+                    from: pair[0],
+                    to: pair[1],
+                    color: PATROL_COLOR,
+                });
+            }
+            if let Some(target) = points.get(patrol.index) {
+                push_rect(&mut lines, *target, Vec2::splat(2.), PATROL_COLOR);
+            }
+        }
+
+        if let Some((transform, entity_instance)) =
+            overlay.selected.and_then(|e| entity_instances.get(e).ok())
+        {
+            push_rect(
+                &mut lines,
+                transform.translation().truncate(),
+                Vec2::new(entity_instance.width as f32, entity_instance.height as f32) / 2.,
+                SELECTED_COLOR,
+            );
+        }
+    }
+
+    if *shown == lines {
+        return;
+    }
+
+    for shapes_entity in &shapes_query {
+        commands.entity(shapes_entity).despawn_descendants();
+        commands.entity(shapes_entity).with_children(|shapes| {
+            for line in &lines {
+                spawn_line(shapes, line);
+            }
+        });
+    }
+
+    *shown = lines;
+}
+
+/// Lines of the inspector panel, for the player and the selected entity.
+fn inspect(world: &World, overlay: &DebugOverlay, player: Option<Entity>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if let Some(level_selection) = world.get_resource::<LevelSelection>() {
+        lines.push(format!("Level: {level_selection:?}"));
+    }
+
+    if let Some(player) = player {
+        if let Some(climber) = world.get::<Climber>(player) {
+            lines.push(format!(
+                "Climber: climbing {}, {} climbables in range",
+                climber.climbing,
+                climber.intersecting_climbables.len()
+            ));
+        }
+        if let Some(ground_detection) = world.get::<GroundDetection>(player) {
+            lines.push(format!(
+                "Ground: on ground {}, ground velocity {}",
+                ground_detection.on_ground, ground_detection.ground_velocity
+            ));
+        }
+    }
+
+    let selected = match overlay.selected {
+        Some(selected) => selected,
+        None => {
+            lines.push("Click an entity to inspect it".to_string());
+            return lines;
+        }
+    };
+
+    if world.get_entity(selected).is_none() {
+        lines.push(format!("{selected:?} was despawned"));
+        return lines;
+    }
+
+    lines.push(String::new());
+    if let Some(entity_instance) = world.get::<EntityInstance>(selected) {
+        lines.push(format!(
+            "{} {} at {}",
+            entity_instance.identifier, entity_instance.iid, entity_instance.grid
+        ));
+        for field_instance in &entity_instance.field_instances {
+            lines.push(format!(
+                "  {}: {:?}",
+                field_instance.identifier, field_instance.value
+            ));
+        }
+    } else {
+        lines.push(format!("{selected:?}"));
+    }
+
+    lines.push("Components:".to_string());
+    let mut components: Vec<String> = world
+        .inspect_entity(selected)
+        .iter()
+        .map(|component_info| get_short_name(component_info.name()))
+        .collect();
+    components.sort();
+    lines.extend(components.into_iter().map(|name| format!("  {name}")));
+
+    lines
+}
+
+/// Rebuilds the inspector panel when what it shows changes.
+pub fn update_debug_panel(
+    world: &World,
+    mut commands: Commands,
+    overlay: Res<DebugOverlay>,
+    asset_server: Res<AssetServer>,
+    players: Query<Entity, With<Player>>,
+    panels: Query<Entity, With<DebugPanel>>,
+    mut shown: Local<Option<Vec<String>>>,
+) {
+    let lines = if overlay.enabled {
+        Some(inspect(world, &overlay, players.iter().next()))
+    } else {
+        None
+    };
+
+    if *shown == lines {
+        return;
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+
+    for panel in &panels {
+        commands.entity(panel).despawn_descendants();
+        let display = if lines.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        commands.entity(panel).insert(panel_style(display));
+
+        if let Some(lines) = &lines {
+            commands.entity(panel).with_children(|panel| {
+                for line in lines {
+                    panel.spawn(TextBundle::from_section(
+                        line.clone(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.,
+                            color: Color::WHITE,
+                        },
+                    ));
+                }
+            });
+        }
+    }
+
+    *shown = lines;
+}
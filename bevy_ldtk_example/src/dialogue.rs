@@ -0,0 +1,457 @@
+//! Npcs and the dialogues they start when the player talks to them.
+//!
+//! Npcs list the ids of their dialogues in their LDtk `dialogues` array,
+//! the first of them whose conditions the player's items meet is shown.
+
+use crate::{
+    components::*, input::*, ldtk_fields::impl_ldtk_fields, resources::load_json_or_default,
+};
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashMap, path::PathBuf};
+
+/// How close the player needs to be to an npc to talk to it, in pixels.
+const NPC_INTERACT_RANGE: f32 = 24.;
+
+/// Something the player's items need to meet for a dialogue or choice to be available.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DialogueCondition {
+    /// At least one of the item
+    HasItem(String),
+    /// None of the item
+    LacksItem(String),
+    /// At least this many of the item
+    HasItems(String, usize),
+}
+
+impl DialogueCondition {
+    pub fn is_met(&self, items: &Items) -> bool {
+        match self {
+            DialogueCondition::HasItem(id) => items.count(id) > 0,
+            DialogueCondition::LacksItem(id) => items.count(id) == 0,
+            DialogueCondition::HasItems(id, count) => items.count(id) >= *count,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialogueChoice {
+    pub text: String,
+    /// The choice is hidden unless all of these are met.
+    pub conditions: Vec<DialogueCondition>,
+    /// Id of the dialogue to continue with, the dialogue ends if there's none.
+    pub next: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialogueLine {
+    /// Who's talking, the npc's name if there's none.
+    pub speaker: Option<String>,
+    pub text: String,
+    /// Choices shown with the line, the player picks one of them to go on.
+    pub choices: Vec<DialogueChoice>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Dialogue {
+    /// The dialogue is skipped unless all of these are met.
+    pub conditions: Vec<DialogueCondition>,
+    pub lines: Vec<DialogueLine>,
+}
+
+impl Dialogue {
+    pub fn is_available(&self, items: &Items) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.is_met(items))
+    }
+}
+
+/// Dialogues keyed by the ids npcs reference in LDtk.
+///
+/// Loaded from `assets/dialogue.json` on startup.
+#[derive(Clone, Debug, Default, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialogueDefinitions {
+    pub dialogues: HashMap<String, Dialogue>,
+}
+
+impl DialogueDefinitions {
+    pub fn path() -> PathBuf {
+        FileAssetIo::get_base_path()
+            .join("assets")
+            .join("dialogue.json")
+    }
+
+    /// Loads the dialogue file, falling back to no dialogues if it's missing or invalid.
+    pub fn load_or_default() -> Self {
+        load_json_or_default(&Self::path(), "dialogues")
+    }
+
+    /// Returns the id of the first of `ids` that's defined and available.
+    pub fn first_available<'a>(
+        &self,
+        ids: impl IntoIterator<Item = &'a String>,
+        items: &Items,
+    ) -> Option<&'a String> {
+        ids.into_iter()
+            .find(|id| match self.dialogues.get(id.as_str()) {
+                Some(dialogue) => dialogue.is_available(items),
+                None => false,
+            })
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Component)]
+pub struct Npc {
+    pub name: String,
+    /// Ids of the npc's dialogues, in the order they're tried in
+    pub dialogues: Vec<String>,
+}
+
+impl_ldtk_fields!(Npc {
+    name: "name",
+    dialogues: "dialogues",
+});
+
+#[derive(Clone, Default, Bundle, LdtkEntity)]
+pub struct NpcBundle {
+    #[sprite_sheet_bundle]
+    #[bundle]
+    pub sprite_sheet_bundle: SpriteSheetBundle,
+    #[from_entity_instance]
+    pub npc: Npc,
+    #[from_entity_instance]
+    pub entity_iid: EntityIid,
+}
+
+/// The dialogue being shown, if any.
+///
+/// `movement` is paused while a dialogue is active.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Resource)]
+pub struct ActiveDialogue {
+    /// Id of the dialogue, `None` while no dialogue is shown
+    pub id: Option<String>,
+    pub line: usize,
+    /// Name of the npc being talked to, for lines without a speaker
+    pub npc_name: String,
+    /// The choices of the current line the player's items allow
+    pub choices: Vec<DialogueChoice>,
+    pub selected: usize,
+}
+
+impl ActiveDialogue {
+    pub fn is_active(&self) -> bool {
+        self.id.is_some()
+    }
+
+    /// Shows the first line of a dialogue, or ends the dialogue if it's undefined or empty.
+    fn start(&mut self, id: &str, definitions: &DialogueDefinitions, items: &Items) {
+        if !definitions.dialogues.contains_key(id) {
+            warn!("Undefined dialogue {id}");
+        }
+
+        self.id = Some(id.to_string());
+        self.show_line(0, definitions, items);
+    }
+
+    fn show_line(&mut self, line: usize, definitions: &DialogueDefinitions, items: &Items) {
+        let current = self
+            .id
+            .as_ref()
+            .and_then(|id| definitions.dialogues.get(id))
+            .and_then(|dialogue| dialogue.lines.get(line));
+
+        match current {
+            Some(current) => {
+                self.line = line;
+                self.choices = current
+                    .choices
+                    .iter()
+                    .filter(|choice| choice.conditions.iter().all(|c| c.is_met(items)))
+                    .cloned()
+                    .collect();
+                self.selected = 0;
+            }
+            None => self.end(),
+        }
+    }
+
+    fn end(&mut self) {
+        *self = ActiveDialogue::default();
+    }
+
+    /// The line being shown.
+    pub fn current_line<'a>(
+        &self,
+        definitions: &'a DialogueDefinitions,
+    ) -> Option<&'a DialogueLine> {
+        definitions
+            .dialogues
+            .get(self.id.as_ref()?)?
+            .lines
+            .get(self.line)
+    }
+}
+
+/// Marks the UI node prompting the player to talk to an npc in range.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct TalkPromptUi;
+
+/// Marks the UI node showing the active dialogue.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DialogueUi;
+
+pub fn setup_dialogue(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Percent(40.),
+                        right: Val::Percent(40.),
+                        bottom: Val::Px(8.),
+                        ..Default::default()
+                    },
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::all(Val::Px(4.)),
+                    ..Default::default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.5).into(),
+                ..Default::default()
+            },
+            TalkPromptUi,
+        ))
+        .with_children(|prompt| {
+            prompt.spawn(TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    font_size: 14.,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Percent(20.),
+                    right: Val::Percent(20.),
+                    bottom: Val::Px(8.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0., 0., 0., 0.8).into(),
+            ..Default::default()
+        },
+        DialogueUi,
+    ));
+}
+
+/// Returns the closest npc in range of the player that has an available dialogue,
+/// along with the id of that dialogue.
+fn npc_in_range<'a>(
+    player_position: Vec2,
+    items: &Items,
+    definitions: &DialogueDefinitions,
+    npcs: impl Iterator<Item = (&'a GlobalTransform, &'a Npc)>,
+) -> Option<(&'a Npc, &'a String)> {
+    npcs.map(|(transform, npc)| {
+        let distance = transform.translation().truncate().distance(player_position);
+        (distance, npc)
+    })
+    .filter(|(distance, _)| *distance <= NPC_INTERACT_RANGE)
+    .filter_map(|(distance, npc)| {
+        definitions
+            .first_available(&npc.dialogues, items)
+            .map(|id| (distance, npc, id))
+    })
+    .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+    .map(|(_, npc, id)| (npc, id))
+}
+
+/// Starts dialogues with npcs the player interacts with, and advances them.
+///
+/// Runs in `CoreStage::PreUpdate` after `update_action_state`,
+/// and consumes the actions while a dialogue is active so nothing else reacts to them.
+#[allow(clippy::type_complexity)]
+pub fn talk_to_npcs(
+    mut actions: ResMut<ActionState>,
+    definitions: Res<DialogueDefinitions>,
+    mut active: ResMut<ActiveDialogue>,
+    players: Query<(&GlobalTransform, &Items), (With<Player>, Without<Dying>)>,
+    npcs: Query<(&GlobalTransform, &Npc)>,
+) {
+    let (player_transform, items) = match players.get_single() {
+        Ok(player) => player,
+        Err(_) => {
+            // The player died or the level changed mid conversation
+            if active.is_active() {
+                active.end();
+            }
+            return;
+        }
+    };
+
+    if !active.is_active() {
+        if !actions.just_pressed(Action::Interact) {
+            return;
+        }
+
+        let player_position = player_transform.translation().truncate();
+        if let Some((npc, id)) = npc_in_range(player_position, items, &definitions, npcs.iter()) {
+            active.npc_name = npc.name.clone();
+            active.start(id, &definitions, items);
+            actions.consume();
+        }
+        return;
+    }
+
+    if actions.axis_just_engaged(ActionAxis::Climb) && !active.choices.is_empty() {
+        let count = active.choices.len();
+        active.selected = if actions.axis(ActionAxis::Climb) > 0. {
+            (active.selected + count - 1) % count
+        } else {
+            (active.selected + 1) % count
+        };
+    }
+
+    if actions.just_pressed(Action::Jump) || actions.just_pressed(Action::Interact) {
+        if active.choices.is_empty() {
+            let next_line = active.line + 1;
+            active.show_line(next_line, &definitions, items);
+        } else {
+            match active.choices[active.selected].next.clone() {
+                Some(next) => active.start(&next, &definitions, items),
+                None => active.end(),
+            }
+        }
+    }
+
+    actions.consume();
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_talk_prompt_ui(
+    definitions: Res<DialogueDefinitions>,
+    active: Res<ActiveDialogue>,
+    players: Query<(&GlobalTransform, &Items), (With<Player>, Without<Dying>)>,
+    npcs: Query<(&GlobalTransform, &Npc)>,
+    mut prompt_ui: Query<(&mut Style, &Children), With<TalkPromptUi>>,
+    mut texts: Query<&mut Text>,
+) {
+    let npc = match players.get_single() {
+        Ok((player_transform, items)) if !active.is_active() => npc_in_range(
+            player_transform.translation().truncate(),
+            items,
+            &definitions,
+            npcs.iter(),
+        ),
+        _ => None,
+    };
+
+    for (mut style, children) in &mut prompt_ui {
+        let display = if npc.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        if style.display != display {
+            style.display = display;
+        }
+
+        if let Some((npc, _)) = npc {
+            let label = if npc.name.is_empty() {
+                "Interact to talk".to_string()
+            } else {
+                format!("Interact to talk to {}", npc.name)
+            };
+
+            for child in children {
+                if let Ok(mut text) = texts.get_mut(*child) {
+                    if text.sections[0].value != label {
+                        text.sections[0].value = label.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn update_dialogue_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    definitions: Res<DialogueDefinitions>,
+    active: Res<ActiveDialogue>,
+    mut dialogue_ui: Query<(Entity, &mut Style), With<DialogueUi>>,
+) {
+    if !active.is_changed() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+    let line = active.current_line(&definitions);
+
+    for (ui_entity, mut style) in &mut dialogue_ui {
+        style.display = if line.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+
+        commands.entity(ui_entity).despawn_descendants();
+        let line = match line {
+            Some(line) => line,
+            None => continue,
+        };
+
+        commands.entity(ui_entity).with_children(|ui| {
+            let speaker = line.speaker.as_ref().unwrap_or(&active.npc_name);
+            if !speaker.is_empty() {
+                ui.spawn(TextBundle::from_section(
+                    speaker.clone(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 14.,
+                        color: Color::YELLOW,
+                    },
+                ));
+            }
+
+            ui.spawn(TextBundle::from_section(
+                line.text.clone(),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 14.,
+                    color: Color::WHITE,
+                },
+            ));
+
+            for (i, choice) in active.choices.iter().enumerate() {
+                let selected = i == active.selected;
+                ui.spawn(TextBundle::from_section(
+                    format!("{} {}", if selected { ">" } else { " " }, choice.text),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 14.,
+                        color: if selected { Color::YELLOW } else { Color::GRAY },
+                    },
+                ));
+            }
+        });
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    components::*,
+    ldtk_fields::{impl_ldtk_fields, LdtkFieldType},
+    resources::InteractTarget,
+    switches::SwitchInputs,
+    tick::TICK_SECONDS,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::FieldInstanceEntityReference, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+/// How far outside of a door the player can still use it, in pixels.
+pub const DOOR_INTERACT_MARGIN: f32 = 8.;
+
+/// Duration of the fade out before and the fade in after going through a door, in seconds.
+pub const DOOR_FADE_TIME: f32 = 0.3;
+
+/// How long to wait for the destination door to spawn before giving up, in seconds.
+const DOOR_ARRIVAL_TIMEOUT: f32 = 3.;
+
+/// The door on the other side, read from an LDtk entity reference.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DoorDestination {
+    pub level_iid: String,
+    pub entity_iid: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Component)]
+pub struct Door {
+    pub destination: Option<DoorDestination>,
+    pub locked: bool,
+}
+
+impl LdtkFieldType for DoorDestination {
+    const EXPECTED: &'static str = FieldInstanceEntityReference::EXPECTED;
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        FieldInstanceEntityReference::from_field_value(value).map(|reference| {
+            reference.map(|reference| DoorDestination {
+                level_iid: reference.level_iid,
+                entity_iid: reference.entity_iid,
+            })
+        })
+    }
+}
+
+// `link` is what the doors of both sample worlds call their entity reference
+impl_ldtk_fields!(Door {
+    destination: "link",
+    locked: "locked",
+});
+
+#[derive(Clone, Default, Bundle)]
+pub struct DoorBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub door: Door,
+    /// Doors with inputs stay locked until their switches are powered
+    pub switch_inputs: SwitchInputs,
+    pub entity_iid: EntityIid,
+}
+
+// Doors are drawn as rectangles in LDtk rather than with a tile,
+// so they're built manually to draw them the same way in game
+impl LdtkEntity for DoorBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> DoorBundle {
+        DoorBundle {
+            sprite_bundle: rectangle_sprite(entity_instance),
+            door: Door::from(entity_instance.clone()),
+            switch_inputs: SwitchInputs::from(entity_instance.clone()),
+            entity_iid: EntityIid::from(entity_instance.clone()),
+        }
+    }
+}
+
+/// Progress of the player through a door.
+///
+/// `update_level_selection` is paused while this isn't `Idle`,
+/// since the player may pass through other levels on the way.
+/// Starting in a level without a Player entity goes from the Player's level to one of its
+/// entities this way.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub enum DoorTransition {
+    #[default]
+    Idle,
+    FadingOut {
+        destination: DoorDestination,
+        elapsed: f32,
+    },
+    /// The destination level is selected, waiting for the destination door and the player to spawn
+    Arriving {
+        destination: DoorDestination,
+        waited: f32,
+    },
+    FadingIn {
+        elapsed: f32,
+    },
+}
+
+impl DoorTransition {
+    pub fn is_active(&self) -> bool {
+        *self != DoorTransition::Idle
+    }
+
+    /// How much the screen is faded out, from 0 to 1.
+    fn fade(&self) -> f32 {
+        match self {
+            DoorTransition::Idle => 0.,
+            DoorTransition::FadingOut { elapsed, .. } => (elapsed / DOOR_FADE_TIME).min(1.),
+            DoorTransition::Arriving { .. } => 1.,
+            DoorTransition::FadingIn { elapsed } => 1. - (elapsed / DOOR_FADE_TIME).min(1.),
+        }
+    }
+}
+
+/// Marks the full screen UI node used to fade out during door transitions.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct DoorFade;
+
+pub fn setup_doors(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                ..Default::default()
+            },
+            background_color: Color::NONE.into(),
+            z_index: ZIndex::Global(i32::MAX),
+            ..Default::default()
+        },
+        DoorFade,
+    ));
+}
+
+/// Starts a transition when the player interacts with an unlocked door that leads somewhere.
+///
+/// Doors listening to switches are locked while their inputs aren't powered.
+pub fn use_doors(
+    interact_target: Res<InteractTarget>,
+    mut door_transition: ResMut<DoorTransition>,
+    doors: Query<(&Door, &SwitchInputs)>,
+) {
+    if door_transition.is_active() {
+        return;
+    }
+
+    let (door, switch_inputs) = match interact_target.0.and_then(|door| doors.get(door).ok()) {
+        Some(door) => door,
+        None => return,
+    };
+
+    if door.locked || !switch_inputs.allows() {
+        info!("This door is locked");
+        return;
+    }
+
+    if let Some(destination) = &door.destination {
+        *door_transition = DoorTransition::FadingOut {
+            destination: destination.clone(),
+            elapsed: 0.,
+        };
+    }
+}
+
+/// Fades out, switches to the destination level, moves the player to the destination door
+/// once it has spawned and fades back in.
+///
+/// Runs in the ticks, so replays reach the destination on the same tick.
+#[allow(clippy::type_complexity)]
+pub fn door_transition(
+    mut door_transition: ResMut<DoorTransition>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut players: Query<
+        (
+            &mut Transform,
+            &GlobalTransform,
+            &mut Velocity,
+            ChangeTrackers<Player>,
+        ),
+        With<Player>,
+    >,
+    doors: Query<(&GlobalTransform, &EntityIid, ChangeTrackers<Door>)>,
+    mut cameras: Query<&mut CameraController>,
+    mut fades: Query<&mut BackgroundColor, With<DoorFade>>,
+) {
+    let next = match &mut *door_transition {
+        DoorTransition::Idle => None,
+        DoorTransition::FadingOut {
+            destination,
+            elapsed,
+        } => {
+            *elapsed += TICK_SECONDS;
+            // When starting in a level, the player has to spawn in its own level first
+            if *elapsed >= DOOR_FADE_TIME && !players.is_empty() {
+                *level_selection = LevelSelection::Iid(destination.level_iid.clone());
+                Some(DoorTransition::Arriving {
+                    destination: destination.clone(),
+                    waited: 0.,
+                })
+            } else {
+                None
+            }
+        }
+        DoorTransition::Arriving {
+            destination,
+            waited,
+        } => {
+            *waited += TICK_SECONDS;
+
+            // Newly spawned doors haven't had their GlobalTransform propagated yet
+            let destination_door = doors
+                .iter()
+                .find(|(_, iid, tracker)| iid.0 == destination.entity_iid && !tracker.is_added())
+                .map(|(transform, _, _)| transform.translation().truncate());
+
+            // When starting in a level, the player spawns along with it
+            let player_spawned = players.iter().any(|(_, _, _, tracker)| !tracker.is_added());
+
+            if let Some(door_position) = destination_door.filter(|_| player_spawned) {
+                for (mut transform, global_transform, mut velocity, _) in &mut players {
+                    // The player's transform is relative to its parent, so move it by the
+                    // difference in world space
+                    let offset = door_position - global_transform.translation().truncate();
+                    transform.translation += offset.extend(0.);
+                    velocity.linvel = Vec2::ZERO;
+                }
+
+                // Snap the camera to the new position instead of panning across the world
+                for mut camera_controller in &mut cameras {
+                    camera_controller.level_iid = None;
+                    camera_controller.transition = None;
+                }
+
+                Some(DoorTransition::FadingIn { elapsed: 0. })
+            } else if *waited >= DOOR_ARRIVAL_TIMEOUT {
+                warn!(
+                    "Destination door {} never spawned in level {}",
+                    destination.entity_iid, destination.level_iid
+                );
+                Some(DoorTransition::FadingIn { elapsed: 0. })
+            } else {
+                None
+            }
+        }
+        DoorTransition::FadingIn { elapsed } => {
+            *elapsed += TICK_SECONDS;
+            if *elapsed >= DOOR_FADE_TIME {
+                Some(DoorTransition::Idle)
+            } else {
+                None
+            }
+        }
+    };
+
+    if let Some(next) = next {
+        *door_transition = next;
+    }
+
+    for mut background_color in &mut fades {
+        background_color.0 = Color::rgba(0., 0., 0., door_transition.fade());
+    }
+}
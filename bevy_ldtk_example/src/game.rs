@@ -0,0 +1,238 @@
+//! Everything the game adds to the app, apart from the window
+//! and the level it starts in.
+
+use crate::{
+    animation, components, debug, dialogue, doors, hot_reload, input, inventory, level_select,
+    minimap, platforms, registry, replay, resources, save, speedrun, switches, systems,
+    tick::{self, AddTickSystem, TickStage},
+    validation,
+};
+use bevy::{input::InputSystem, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+/// The game's plugins, resources and systems.
+///
+/// Expects `CurrentProject`, `LevelSelection` and `DoorTransition` to be inserted by the app,
+/// and `ReplayPlayback` when playing back a replay.
+/// Saves go to the `SaveDirectory` inserted before the plugin, if any.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        // Inserted before the plugin to keep the saves somewhere else, like in tests
+        app.init_resource::<save::SaveDirectory>();
+        let saves = app.world.resource::<save::SaveDirectory>().0.clone();
+
+        app.add_plugin(LdtkPlugin)
+            // Physics step in the ticks instead, see `tick::tick_schedule`
+            .add_plugin(
+                RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                    .with_default_system_setup(false),
+            )
+            .add_stage_after(CoreStage::Update, tick::FixedUpdate, tick::tick_schedule())
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+                ),
+            )
+            .insert_resource(RapierConfiguration {
+                gravity: Vec2::new(0.0, -2000.0),
+                timestep_mode: TimestepMode::Fixed {
+                    dt: tick::TICK_SECONDS,
+                    substeps: 1,
+                },
+                ..Default::default()
+            })
+            .init_resource::<level_select::LevelMenu>()
+            .init_resource::<resources::PlayerMovementConfig>()
+            .init_resource::<resources::CameraConfig>()
+            .init_resource::<resources::CombatConfig>()
+            .init_resource::<resources::LiquidConfig>()
+            .init_resource::<resources::RespawnPoint>()
+            .init_resource::<resources::WorldProgress>()
+            .init_resource::<resources::InteractTarget>()
+            .init_resource::<validation::InvalidEntities>()
+            .insert_resource(validation::ValidationRules::game())
+            .init_resource::<save::SaveSlot>()
+            .init_resource::<save::PendingLoad>()
+            .init_resource::<hot_reload::PendingReload>()
+            .insert_resource(input::InputBindings::load_or_default())
+            .init_resource::<input::ActionState>()
+            .init_resource::<tick::TickActions>()
+            .insert_resource(inventory::ItemDefinitions::load_or_default())
+            .insert_resource(animation::Animations::load_or_default())
+            .insert_resource(dialogue::DialogueDefinitions::load_or_default())
+            .init_resource::<dialogue::ActiveDialogue>()
+            .init_resource::<debug::DebugOverlay>()
+            .init_resource::<speedrun::RunTimer>()
+            .insert_resource(speedrun::BestSplits::load_or_default(&saves))
+            .init_resource::<replay::ReplayRecorder>()
+            .init_resource::<replay::ReplayPlayback>()
+            .insert_resource(replay::GhostRun::load_or_default(&saves))
+            .insert_resource(LdtkSettings {
+                level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
+                    load_level_neighbors: true,
+                },
+                set_clear_color: SetClearColor::FromLevelBackground,
+                ..Default::default()
+            })
+            .add_event::<components::TerrainEdit>()
+            .add_event::<save::SaveGame>()
+            .add_event::<save::LoadGame>()
+            .add_event::<level_select::StartLevel>()
+            .add_startup_system(systems::setup)
+            .add_startup_system(inventory::setup_inventory)
+            .add_startup_system(doors::setup_doors)
+            .add_startup_system(level_select::setup_level_menu)
+            .add_startup_system(minimap::setup_minimap)
+            .add_startup_system(dialogue::setup_dialogue)
+            .add_startup_system(speedrun::setup_run_timer)
+            .add_startup_system(debug::setup_debug_overlay)
+            .add_system_to_stage(CoreStage::First, hot_reload::snapshot_player_on_reload)
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                input::update_action_state.after(InputSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                level_select::level_menu.after(input::update_action_state),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                dialogue::talk_to_npcs.after(level_select::level_menu),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                tick::buffer_tick_actions.after(dialogue::talk_to_npcs),
+            )
+            .add_tick_system_to_stage(TickStage::First, tick::begin_tick)
+            .add_tick_system_to_stage(
+                TickStage::First,
+                replay::play_replay.after(tick::begin_tick),
+            )
+            .add_tick_system_to_stage(TickStage::Last, systems::detect_climb_range)
+            .add_tick_system_to_stage(TickStage::Last, replay::record_replay)
+            .add_tick_system_to_stage(TickStage::Last, replay::check_replay_sync)
+            .add_tick_system_to_stage(
+                TickStage::Last,
+                replay::move_ghost.after(replay::record_replay),
+            )
+            .add_tick_system_to_stage(
+                TickStage::Last,
+                tick::end_tick
+                    .after(replay::record_replay)
+                    .after(replay::check_replay_sync)
+                    .after(replay::move_ghost),
+            )
+            .add_system(level_select::update_level_menu_ui)
+            .add_system(dialogue::update_talk_prompt_ui)
+            .add_system(dialogue::update_dialogue_ui)
+            .add_system(level_select::start_level)
+            .add_system(validation::validate_levels.before(validation::skip_invalid_entities))
+            .add_system(validation::skip_invalid_entities)
+            .add_system(systems::dig_and_build.before(systems::edit_terrain))
+            .add_system(systems::edit_terrain.before(systems::spawn_wall_collision))
+            .add_system(systems::spawn_wall_collision)
+            .add_system(systems::spawn_ladder_collision)
+            .add_system(systems::spawn_terrain_volumes)
+            .add_tick_system(systems::movement)
+            .add_tick_system(systems::climb_ladders.after(systems::movement))
+            .add_tick_system(systems::detect_liquids)
+            .add_tick_system(
+                systems::swim
+                    .after(systems::movement)
+                    .after(systems::detect_liquids),
+            )
+            .add_tick_system(systems::apply_liquids.after(systems::swim))
+            .add_tick_system(systems::ignore_gravity_if_climbing)
+            .add_tick_system(systems::enemy_ai.before(systems::patrol))
+            .add_tick_system(systems::patrol)
+            .add_tick_system(platforms::trigger_platforms.before(systems::patrol))
+            .add_tick_system(
+                platforms::ride_platforms
+                    .after(systems::patrol)
+                    .before(systems::movement),
+            )
+            .add_tick_system(platforms::one_way_platforms.after(systems::climb_ladders))
+            .add_tick_system(
+                systems::sync_ground_sensor_groups
+                    .after(systems::climb_ladders)
+                    .after(platforms::one_way_platforms),
+            )
+            .add_system(animation::select_animation_state)
+            .add_system(animation::animate_sprites.after(animation::select_animation_state))
+            .add_system(systems::camera_follow_player)
+            .add_system(systems::update_level_selection)
+            .add_system(minimap::discover_levels.after(systems::update_level_selection))
+            .add_system(minimap::draw_minimap.after(minimap::discover_levels))
+            .add_system(minimap::update_minimap_ui.after(minimap::draw_minimap))
+            .add_tick_system(switches::press_plates)
+            .add_tick_system(systems::select_interact_target)
+            .add_tick_system(switches::use_levers.after(systems::select_interact_target))
+            .add_tick_system(
+                switches::update_switch_inputs
+                    .after(switches::press_plates)
+                    .after(switches::use_levers),
+            )
+            .add_tick_system(switches::update_gates.after(switches::update_switch_inputs))
+            .add_system(switches::update_switch_sprites)
+            .add_tick_system(
+                doors::use_doors
+                    .after(systems::select_interact_target)
+                    .after(switches::update_switch_inputs),
+            )
+            .add_tick_system(doors::door_transition.after(doors::use_doors))
+            .add_system(debug::toggle_debug_overlay)
+            .add_system(
+                debug::select_debug_entity
+                    .after(debug::toggle_debug_overlay)
+                    .before(systems::dig_and_build),
+            )
+            .add_system(debug::draw_debug_overlay.after(debug::select_debug_entity))
+            .add_system(debug::update_debug_panel.after(debug::select_debug_entity))
+            .add_system(systems::spawn_ground_sensor)
+            .add_tick_system_to_stage(TickStage::Last, systems::ground_detection)
+            .add_tick_system_to_stage(
+                TickStage::Last,
+                systems::update_on_ground.after(systems::ground_detection),
+            )
+            .add_system(systems::spawn_wall_sensors)
+            .add_tick_system_to_stage(TickStage::Last, systems::wall_detection)
+            .add_tick_system_to_stage(
+                TickStage::Last,
+                systems::update_on_wall.after(systems::wall_detection),
+            )
+            .add_system(systems::restart_level)
+            .add_system(speedrun::start_run_timer.after(systems::restart_level))
+            .add_tick_system(speedrun::tick_run_timer)
+            .add_system(speedrun::record_splits.after(systems::update_level_selection))
+            .add_system(speedrun::update_run_timer_ui.after(speedrun::record_splits))
+            .add_tick_system(systems::enemy_contact)
+            .add_tick_system(systems::hazard_contact.after(systems::enemy_contact))
+            .add_tick_system(systems::update_invincibility)
+            .add_tick_system(systems::death_sequence)
+            .add_system(systems::update_respawn_point)
+            .add_system(
+                hot_reload::restore_player_after_reload.after(systems::update_respawn_point),
+            )
+            .add_system(systems::apply_world_progress)
+            .add_system(save::save_actions)
+            .add_system(save::save_game.after(save::save_actions))
+            .add_system(save::load_game.after(save::save_actions))
+            // After the respawn point is updated, so a loaded one isn't replaced with a checkpoint
+            // near where the player was
+            .add_system(
+                save::apply_pending_load
+                    .after(save::load_game)
+                    .after(systems::update_respawn_point),
+            )
+            .add_system(inventory::build_item_icons)
+            .add_tick_system(inventory::loot_chests.after(systems::select_interact_target))
+            .add_system(inventory::update_inventory_ui);
+
+        registry::register_bundles(app);
+    }
+}
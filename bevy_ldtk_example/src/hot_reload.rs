@@ -0,0 +1,101 @@
+//! Keeping the player's state when the LDtk project is saved while the game is running.
+//!
+//! `bevy_ecs_ldtk` respawns the levels when the project file changes, usually including the
+//! worldly player, which would otherwise start over at its Player entity.
+//! Walls and ladders get new colliders as their cells spawn again.
+
+use crate::{components::*, resources::*};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+/// How long to wait for the player to spawn again after a reload, in seconds.
+///
+/// The player is left alone if it doesn't, the snapshot would otherwise be applied
+/// whenever it spawns next.
+const RELOAD_TIMEOUT: f32 = 3.;
+
+/// The player's state from before the project was reloaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerSnapshot {
+    /// Relative to the player's parent, like its `Transform`
+    pub translation: Vec2,
+    pub velocity: Vec2,
+    pub items: Items,
+    pub health: Health,
+    /// Restored over the spawn point `update_respawn_point` picks for the respawned player
+    pub respawn_point: RespawnPoint,
+}
+
+/// A snapshot waiting for the player to spawn again after a reload.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct PendingReload(pub Option<PlayerSnapshot>);
+
+/// Takes a snapshot of the player when its project is modified.
+///
+/// Runs in `CoreStage::First`, before `bevy_ecs_ldtk` despawns the levels in response.
+#[allow(clippy::type_complexity)]
+pub fn snapshot_player_on_reload(
+    mut ldtk_events: EventReader<AssetEvent<LdtkAsset>>,
+    mut pending_reload: ResMut<PendingReload>,
+    respawn_point: Res<RespawnPoint>,
+    player_query: Query<(&Transform, &Velocity, &Items, &Health), (With<Player>, Without<Dying>)>,
+) {
+    let modified = ldtk_events
+        .iter()
+        .any(|event| matches!(event, AssetEvent::Modified { .. }));
+
+    if !modified {
+        return;
+    }
+
+    info!("LDtk project modified, reloading");
+
+    // Reloading again before the player is back keeps the first snapshot,
+    // and a dying player might as well start over
+    if let Ok((transform, velocity, items, health)) = player_query.get_single() {
+        pending_reload.0 = Some(PlayerSnapshot {
+            translation: transform.translation.truncate(),
+            velocity: velocity.linvel,
+            items: items.clone(),
+            health: health.clone(),
+            respawn_point: respawn_point.clone(),
+        });
+    }
+}
+
+/// Puts the snapshot back on the player once it has spawned again.
+#[allow(clippy::type_complexity)]
+pub fn restore_player_after_reload(
+    time: Res<Time>,
+    mut waited: Local<f32>,
+    mut pending_reload: ResMut<PendingReload>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    mut player_query: Query<
+        (&mut Transform, &mut Velocity, &mut Items, &mut Health),
+        Added<Player>,
+    >,
+) {
+    if pending_reload.0.is_none() {
+        *waited = 0.;
+        return;
+    }
+
+    *waited += time.delta_seconds();
+
+    for (mut transform, mut velocity, mut items, mut health) in &mut player_query {
+        if let Some(snapshot) = pending_reload.0.take() {
+            transform.translation.x = snapshot.translation.x;
+            transform.translation.y = snapshot.translation.y;
+            velocity.linvel = snapshot.velocity;
+            *items = snapshot.items;
+            *health = snapshot.health;
+            *respawn_point = snapshot.respawn_point;
+        }
+    }
+
+    if pending_reload.0.is_some() && *waited >= RELOAD_TIMEOUT {
+        info!("The player didn't respawn after reloading, keeping it as it is");
+        pending_reload.0 = None;
+    }
+}
@@ -0,0 +1,378 @@
+use crate::resources::load_json_or_default;
+use bevy::{asset::FileAssetIo, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+/// Digital actions the game reacts to, independent of the device they came from.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Jump,
+    Interact,
+    Restart,
+    Debug,
+    Save,
+    Load,
+    NextSaveSlot,
+    LevelMenu,
+    Map,
+}
+
+/// Analog actions in the range -1..=1.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash, Serialize, Deserialize)]
+pub enum ActionAxis {
+    /// Negative is left, positive is right
+    Move,
+    /// Negative is down, positive is up
+    Climb,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ButtonBinding {
+    pub keys: Vec<KeyCode>,
+    pub gamepad_buttons: Vec<GamepadButtonType>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisBinding {
+    pub negative_keys: Vec<KeyCode>,
+    pub positive_keys: Vec<KeyCode>,
+    pub negative_gamepad_buttons: Vec<GamepadButtonType>,
+    pub positive_gamepad_buttons: Vec<GamepadButtonType>,
+    pub gamepad_axes: Vec<GamepadAxisType>,
+}
+
+/// Maps keys and gamepad inputs to actions.
+///
+/// Loaded from `assets/input_bindings.json` on startup, so controls can be rebound
+/// without recompiling. Actions missing from the file keep the default bindings below.
+#[derive(Clone, Debug, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputBindings {
+    /// Stick values with a smaller magnitude than this are treated as 0.
+    pub deadzone: f32,
+    pub buttons: HashMap<Action, ButtonBinding>,
+    pub axes: HashMap<ActionAxis, AxisBinding>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let buttons = HashMap::from([
+            (
+                Action::Jump,
+                ButtonBinding {
+                    keys: vec![KeyCode::Space],
+                    gamepad_buttons: vec![GamepadButtonType::South],
+                },
+            ),
+            (
+                Action::Interact,
+                ButtonBinding {
+                    keys: vec![KeyCode::E],
+                    gamepad_buttons: vec![GamepadButtonType::West],
+                },
+            ),
+            (
+                Action::Restart,
+                ButtonBinding {
+                    keys: vec![KeyCode::R],
+                    gamepad_buttons: vec![GamepadButtonType::Select],
+                },
+            ),
+            (
+                Action::Debug,
+                ButtonBinding {
+                    keys: vec![KeyCode::P],
+                    gamepad_buttons: vec![GamepadButtonType::North],
+                },
+            ),
+            (
+                Action::Save,
+                ButtonBinding {
+                    keys: vec![KeyCode::F5],
+                    gamepad_buttons: vec![],
+                },
+            ),
+            (
+                Action::Load,
+                ButtonBinding {
+                    keys: vec![KeyCode::F9],
+                    gamepad_buttons: vec![],
+                },
+            ),
+            (
+                Action::NextSaveSlot,
+                ButtonBinding {
+                    keys: vec![KeyCode::F6],
+                    gamepad_buttons: vec![],
+                },
+            ),
+            (
+                Action::LevelMenu,
+                ButtonBinding {
+                    keys: vec![KeyCode::Tab],
+                    gamepad_buttons: vec![GamepadButtonType::Start],
+                },
+            ),
+            (
+                Action::Map,
+                ButtonBinding {
+                    keys: vec![KeyCode::M],
+                    gamepad_buttons: vec![GamepadButtonType::LeftThumb],
+                },
+            ),
+        ]);
+
+        let axes = HashMap::from([
+            (
+                ActionAxis::Move,
+                AxisBinding {
+                    negative_keys: vec![KeyCode::A, KeyCode::Left],
+                    positive_keys: vec![KeyCode::D, KeyCode::Right],
+                    negative_gamepad_buttons: vec![GamepadButtonType::DPadLeft],
+                    positive_gamepad_buttons: vec![GamepadButtonType::DPadRight],
+                    gamepad_axes: vec![GamepadAxisType::LeftStickX],
+                },
+            ),
+            (
+                ActionAxis::Climb,
+                AxisBinding {
+                    negative_keys: vec![KeyCode::S, KeyCode::Down],
+                    positive_keys: vec![KeyCode::W, KeyCode::Up],
+                    negative_gamepad_buttons: vec![GamepadButtonType::DPadDown],
+                    positive_gamepad_buttons: vec![GamepadButtonType::DPadUp],
+                    gamepad_axes: vec![GamepadAxisType::LeftStickY],
+                },
+            ),
+        ]);
+
+        InputBindings {
+            deadzone: 0.2,
+            buttons,
+            axes,
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn path() -> PathBuf {
+        FileAssetIo::get_base_path()
+            .join("assets")
+            .join("input_bindings.json")
+    }
+
+    /// Loads the bindings file, falling back to the default bindings if it's missing or invalid.
+    pub fn load_or_default() -> Self {
+        let loaded: InputBindings = load_json_or_default(&Self::path(), "input bindings");
+
+        // `#[serde(default)]` only fills in missing maps, not missing actions within them
+        let mut bindings = InputBindings {
+            deadzone: loaded.deadzone,
+            ..Default::default()
+        };
+        bindings.buttons.extend(loaded.buttons);
+        bindings.axes.extend(loaded.axes);
+        bindings
+    }
+}
+
+/// Scales values outside the deadzone back to the full 0..=1 range,
+/// so there's no jump in speed when the stick leaves the deadzone.
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone {
+        0.
+    } else {
+        value.signum() * ((value.abs() - deadzone) / (1. - deadzone)).min(1.)
+    }
+}
+
+/// The actions of a single tick, as stored in replays.
+///
+/// Actions are sorted, so the same input always compares equal.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TickInput {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pressed: Vec<Action>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub just_pressed: Vec<Action>,
+    /// Axes that aren't at rest
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub axes: Vec<(ActionAxis, f32)>,
+}
+
+/// The state of every action for the current frame.
+///
+/// While a tick runs, this holds the actions of the tick instead, see `TickActions`.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    axes: HashMap<ActionAxis, f32>,
+    previous_axes: HashMap<ActionAxis, f32>,
+    /// Set by `consume`, until the next frame
+    consumed: bool,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        !self.consumed && self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        !self.consumed && self.just_pressed.contains(&action)
+    }
+
+    pub fn axis(&self, axis: ActionAxis) -> f32 {
+        if self.consumed {
+            return 0.;
+        }
+        self.axes.get(&axis).copied().unwrap_or(0.)
+    }
+
+    /// Whether the axis left its neutral position this frame, the analog equivalent of `just_pressed`.
+    pub fn axis_just_engaged(&self, axis: ActionAxis) -> bool {
+        let previous = self.previous_axes.get(&axis).copied().unwrap_or(0.);
+        previous == 0. && self.axis(axis) != 0.
+    }
+
+    /// Hides the actions from the systems that run after this for the rest of the frame,
+    /// for menus that take over the input.
+    ///
+    /// Unlike releasing the actions, buttons that are still held aren't pressed again next frame.
+    pub fn consume(&mut self) {
+        self.consumed = true;
+    }
+
+    /// Sets the state of a button action, updating `just_pressed`.
+    ///
+    /// Expects `clear_just_pressed` to be called once at the start of each frame.
+    pub fn set_pressed(&mut self, action: Action, pressed: bool) {
+        if pressed {
+            if self.pressed.insert(action) {
+                self.just_pressed.insert(action);
+            }
+        } else {
+            self.pressed.remove(&action);
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: ActionAxis, value: f32) {
+        self.axes.insert(axis, value.clamp(-1., 1.));
+    }
+
+    pub fn clear_just_pressed(&mut self) {
+        self.just_pressed.clear();
+        self.previous_axes = self.axes.clone();
+        self.consumed = false;
+    }
+
+    /// Adds the actions of a frame to the actions buffered for the next tick.
+    ///
+    /// Buttons pressed in any frame since the last tick stay just pressed until the tick runs,
+    /// held buttons and axes are taken from the latest frame.
+    pub fn accumulate(&mut self, frame: &ActionState) {
+        // Actions hidden by a menu count as released
+        if frame.consumed {
+            self.pressed.clear();
+            self.axes.clear();
+            return;
+        }
+
+        self.pressed = frame.pressed.clone();
+        self.just_pressed.extend(frame.just_pressed.iter().copied());
+        self.axes = frame.axes.clone();
+    }
+
+    pub fn tick_input(&self) -> TickInput {
+        let mut pressed: Vec<Action> = self.pressed.iter().copied().collect();
+        pressed.sort();
+        let mut just_pressed: Vec<Action> = self.just_pressed.iter().copied().collect();
+        just_pressed.sort();
+        let mut axes: Vec<(ActionAxis, f32)> = self
+            .axes
+            .iter()
+            .filter(|(_, value)| **value != 0.)
+            .map(|(axis, value)| (*axis, *value))
+            .collect();
+        axes.sort_by_key(|(axis, _)| *axis);
+
+        TickInput {
+            pressed,
+            just_pressed,
+            axes,
+        }
+    }
+
+    /// Replaces the actions with the input of a tick,
+    /// expects `clear_just_pressed` to be called before each tick like before each frame.
+    pub fn set_tick_input(&mut self, input: &TickInput) {
+        self.pressed = input.pressed.iter().copied().collect();
+        self.just_pressed = input.just_pressed.iter().copied().collect();
+        self.axes = input.axes.iter().copied().collect();
+        self.consumed = false;
+    }
+}
+
+/// Translates raw keyboard and gamepad input into the `ActionState`.
+///
+/// Runs in `CoreStage::PreUpdate` after bevy's own input systems,
+/// so every system in `CoreStage::Update` sees the same actions.
+pub fn update_action_state(
+    bindings: Res<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut action_state: ResMut<ActionState>,
+) {
+    action_state.clear_just_pressed();
+
+    let button_pressed = |button_type: &GamepadButtonType| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, *button_type)))
+    };
+
+    for (action, binding) in &bindings.buttons {
+        let pressed = keys.any_pressed(binding.keys.iter().copied())
+            || binding.gamepad_buttons.iter().any(button_pressed);
+
+        action_state.set_pressed(*action, pressed);
+    }
+
+    for (axis, binding) in &bindings.axes {
+        let mut digital = 0.;
+        if keys.any_pressed(binding.positive_keys.iter().copied())
+            || binding.positive_gamepad_buttons.iter().any(button_pressed)
+        {
+            digital += 1.;
+        }
+        if keys.any_pressed(binding.negative_keys.iter().copied())
+            || binding.negative_gamepad_buttons.iter().any(button_pressed)
+        {
+            digital -= 1.;
+        }
+
+        // Use whichever input is pushed the furthest,
+        // so a resting stick doesn't cancel out the keyboard
+        let mut value: f32 = digital;
+        for gamepad in gamepads.iter() {
+            for axis_type in &binding.gamepad_axes {
+                if let Some(stick) = gamepad_axes.get(GamepadAxis::new(gamepad, *axis_type)) {
+                    let stick = apply_deadzone(stick, bindings.deadzone);
+                    if stick.abs() > value.abs() {
+                        value = stick;
+                    }
+                }
+            }
+        }
+
+        action_state.set_axis(*axis, value);
+    }
+}
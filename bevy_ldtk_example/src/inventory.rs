@@ -0,0 +1,307 @@
+use crate::{components::*, resources::*};
+use bevy::{
+    asset::FileAssetIo,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashMap, path::PathBuf};
+
+/// How close the player needs to be to a chest to loot it, in pixels.
+pub const CHEST_INTERACT_RANGE: f32 = 24.;
+
+/// What an item does when it's used.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ItemEffect {
+    #[default]
+    None,
+    Heal(i32),
+}
+
+impl ItemEffect {
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            ItemEffect::None => None,
+            ItemEffect::Heal(amount) => Some(format!("heals {amount}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemDefinition {
+    pub name: String,
+    /// Index of the icon's tile in the icon sheet, like the `tileId` of LDtk enum values.
+    pub icon: Option<u32>,
+    /// Stackable items are shown once with a count instead of once per item.
+    pub stackable: bool,
+    pub effect: ItemEffect,
+}
+
+/// Item definitions keyed by the values of the LDtk `Item` enum.
+///
+/// Loaded from `assets/items.json` on startup.
+#[derive(Clone, Debug, Default, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemDefinitions {
+    /// Path of the icon sheet, relative to the assets folder.
+    pub icon_sheet: String,
+    /// Width and height of a single icon in the sheet, in pixels.
+    pub icon_size: u32,
+    pub items: HashMap<String, ItemDefinition>,
+}
+
+impl ItemDefinitions {
+    pub fn path() -> PathBuf {
+        FileAssetIo::get_base_path()
+            .join("assets")
+            .join("items.json")
+    }
+
+    /// Loads the definitions file, falling back to no definitions if it's missing or invalid.
+    pub fn load_or_default() -> Self {
+        load_json_or_default(&Self::path(), "item definitions")
+    }
+
+    /// Returns the definition for an item id, or a plain stackable item named after the id
+    /// if the id isn't defined.
+    pub fn get(&self, id: &str) -> ItemDefinition {
+        self.items
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| ItemDefinition {
+                name: id.replace('_', " "),
+                stackable: true,
+                ..Default::default()
+            })
+    }
+}
+
+/// Item icons cut out of the icon sheet.
+///
+/// bevy's UI can't draw a region of a texture atlas,
+/// so every icon is copied into its own image once the sheet has loaded.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ItemIcons {
+    sheet: Handle<Image>,
+    icons: HashMap<String, Handle<Image>>,
+    /// Set once the icons are built, or the sheet turned out to be unusable
+    built: bool,
+}
+
+impl ItemIcons {
+    pub fn get(&self, id: &str) -> Option<Handle<Image>> {
+        self.icons.get(id).cloned()
+    }
+}
+
+/// Marks the UI node listing the player's items.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct InventoryUi;
+
+pub fn setup_inventory(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    definitions: Res<ItemDefinitions>,
+) {
+    commands.insert_resource(ItemIcons {
+        sheet: asset_server.load(definitions.icon_sheet.as_str()),
+        ..Default::default()
+    });
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(8.),
+                    top: Val::Px(8.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(4.)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..Default::default()
+        },
+        InventoryUi,
+    ));
+}
+
+pub fn build_item_icons(
+    definitions: Res<ItemDefinitions>,
+    mut item_icons: ResMut<ItemIcons>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if item_icons.built {
+        return;
+    }
+
+    let sheet = match images.get(&item_icons.sheet) {
+        Some(sheet) => sheet,
+        None => return,
+    };
+    item_icons.built = true;
+
+    let sheet_width = sheet.texture_descriptor.size.width;
+    let format = sheet.texture_descriptor.format;
+    let size = definitions.icon_size;
+    let columns = sheet_width.checked_div(size).unwrap_or(0);
+
+    // Images are loaded as 8 bit rgba, which keeps copying rows simple
+    if format != TextureFormat::Rgba8UnormSrgb {
+        warn!("Unsupported item icon sheet {}", definitions.icon_sheet);
+        return;
+    }
+
+    if columns == 0 {
+        warn!(
+            "Icon size {size} doesn't fit in the {sheet_width} pixel wide icon sheet {}",
+            definitions.icon_sheet
+        );
+        return;
+    }
+
+    let sheet_data = &sheet.data;
+    let mut icon_data = Vec::new();
+
+    for (id, definition) in &definitions.items {
+        if let Some(icon) = definition.icon {
+            let left = (icon % columns * size) as usize;
+            let top = (icon / columns * size) as usize;
+
+            let mut data = Vec::with_capacity((size * size * 4) as usize);
+            for y in top..top + size as usize {
+                let start = (y * sheet_width as usize + left) * 4;
+                match sheet_data.get(start..start + size as usize * 4) {
+                    Some(row) => data.extend_from_slice(row),
+                    None => break,
+                }
+            }
+
+            if data.len() != (size * size * 4) as usize {
+                warn!("Icon {icon} of item {id} is outside of the icon sheet");
+                continue;
+            }
+
+            icon_data.push((id.clone(), data));
+        }
+    }
+
+    item_icons.icons = icon_data
+        .into_iter()
+        .map(|(id, data)| {
+            let image = Image::new(
+                Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                format,
+            );
+            (id, images.add(image))
+        })
+        .collect();
+}
+
+/// Moves the items of the chest the player interacts with into the player's inventory.
+#[allow(clippy::type_complexity)]
+pub fn loot_chests(
+    interact_target: Res<InteractTarget>,
+    mut progress: ResMut<WorldProgress>,
+    mut players: Query<&mut Items, With<Player>>,
+    mut chests: Query<(&mut Items, &EntityIid), (With<Chest>, Without<Player>)>,
+) {
+    let (mut chest_items, iid) = match interact_target
+        .0
+        .and_then(|chest| chests.get_mut(chest).ok())
+    {
+        Some(chest) => chest,
+        None => return,
+    };
+
+    for mut player_items in &mut players {
+        player_items.take_all(&mut chest_items);
+        progress.opened_chests.insert(iid.0.clone());
+    }
+}
+
+pub fn update_inventory_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    definitions: Res<ItemDefinitions>,
+    item_icons: Res<ItemIcons>,
+    players: Query<(&Items, ChangeTrackers<Items>), With<Player>>,
+    inventory_ui: Query<Entity, With<InventoryUi>>,
+) {
+    let (items, items_tracker) = match players.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    if !items_tracker.is_changed() && !item_icons.is_changed() {
+        return;
+    }
+
+    // Group stackable items, keeping the order they were picked up in
+    let mut rows: Vec<(&String, ItemDefinition, usize)> = Vec::new();
+    for id in items.iter() {
+        let definition = definitions.get(id);
+        match rows.iter_mut().find(|(row_id, _, _)| *row_id == id) {
+            Some((_, _, count)) if definition.stackable => *count += 1,
+            _ => rows.push((id, definition, 1)),
+        }
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+
+    for ui_entity in &inventory_ui {
+        commands.entity(ui_entity).despawn_descendants();
+        commands.entity(ui_entity).with_children(|ui| {
+            for (id, definition, count) in &rows {
+                ui.spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    if let Some(icon) = item_icons.get(id) {
+                        row.spawn(ImageBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(16.), Val::Px(16.)),
+                                margin: UiRect::right(Val::Px(4.)),
+                                ..Default::default()
+                            },
+                            image: icon.into(),
+                            ..Default::default()
+                        });
+                    }
+
+                    let mut label = definition.name.clone();
+                    if *count > 1 {
+                        label.push_str(&format!(" x{count}"));
+                    }
+                    if let Some(effect) = definition.effect.describe() {
+                        label.push_str(&format!(" ({effect})"));
+                    }
+
+                    row.spawn(TextBundle::from_section(
+                        label,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.,
+                            color: Color::WHITE,
+                        },
+                    ));
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,289 @@
+//! Reading components from the custom fields of LDtk entities.
+//!
+//! Components list which field each of their members is read from with `impl_ldtk_fields!`,
+//! instead of matching on `FieldValue`s by hand. Enums are read from LDtk enums with
+//! `impl_ldtk_enum_field!`.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::FieldInstanceEntityReference, prelude::*};
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum FieldError {
+    #[error("entity has no field `{identifier}`")]
+    Missing { identifier: String },
+    #[error("field `{identifier}` is null")]
+    Null { identifier: String },
+    #[error("field `{identifier}` is a {found}, expected {expected}")]
+    WrongType {
+        identifier: String,
+        expected: &'static str,
+        found: String,
+    },
+}
+
+/// A Rust type an LDtk field value can be converted to.
+pub trait LdtkFieldType: Sized {
+    /// The LDtk field types this accepts, for error messages.
+    const EXPECTED: &'static str;
+
+    /// Returns `None` if the value has the wrong type, and `Some(None)` if it's null.
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>>;
+}
+
+impl LdtkFieldType for i32 {
+    const EXPECTED: &'static str = "Int";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl LdtkFieldType for f32 {
+    const EXPECTED: &'static str = "Float or Int";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Float(value) => Some(*value),
+            FieldValue::Int(value) => Some(value.map(|v| v as f32)),
+            _ => None,
+        }
+    }
+}
+
+impl LdtkFieldType for bool {
+    const EXPECTED: &'static str = "Bool";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Bool(value) => Some(Some(*value)),
+            _ => None,
+        }
+    }
+}
+
+/// Enum values are read as the name of the value.
+impl LdtkFieldType for String {
+    const EXPECTED: &'static str = "String, Enum or FilePath";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::String(value) | FieldValue::Enum(value) | FieldValue::FilePath(value) => {
+                Some(value.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl LdtkFieldType for Color {
+    const EXPECTED: &'static str = "Color";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Color(value) => Some(Some(*value)),
+            _ => None,
+        }
+    }
+}
+
+/// Points are read in grid coordinates, like LDtk stores them.
+impl LdtkFieldType for IVec2 {
+    const EXPECTED: &'static str = "Point";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Point(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl LdtkFieldType for FieldInstanceEntityReference {
+    const EXPECTED: &'static str = "EntityRef";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::EntityRef(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Lets a null value clear an optional member instead of keeping its default.
+impl<T: LdtkFieldType> LdtkFieldType for Option<T> {
+    const EXPECTED: &'static str = T::EXPECTED;
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        T::from_field_value(value).map(Some)
+    }
+}
+
+/// Implements `LdtkFieldType` for `Vec`s of array fields, skipping null elements.
+macro_rules! impl_array_field_type {
+    ($type:ty, $expected:literal, $($variant:ident)|+) => {
+        impl LdtkFieldType for Vec<$type> {
+            const EXPECTED: &'static str = $expected;
+
+            fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+                match value {
+                    $(FieldValue::$variant(values))|+ => {
+                        Some(Some(values.iter().flatten().cloned().collect()))
+                    }
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_array_field_type!(i32, "Array<Int>", Ints);
+impl_array_field_type!(f32, "Array<Float>", Floats);
+impl_array_field_type!(
+    String,
+    "Array<String>, Array<Enum> or Array<FilePath>",
+    Strings | Enums | FilePaths
+);
+impl_array_field_type!(IVec2, "Array<Point>", Points);
+impl_array_field_type!(FieldInstanceEntityReference, "Array<EntityRef>", EntityRefs);
+
+impl LdtkFieldType for Vec<Color> {
+    const EXPECTED: &'static str = "Array<Color>";
+
+    fn from_field_value(value: &FieldValue) -> Option<Option<Self>> {
+        match value {
+            FieldValue::Colors(values) => Some(Some(values.clone())),
+            _ => None,
+        }
+    }
+}
+
+pub trait EntityInstanceFields {
+    /// Reads a field, returning `None` if the entity doesn't have it or it's null.
+    fn field<T: LdtkFieldType>(&self, identifier: &str) -> Result<Option<T>, FieldError>;
+
+    /// Reads a field that has to be set.
+    fn required_field<T: LdtkFieldType>(&self, identifier: &str) -> Result<T, FieldError>;
+}
+
+impl EntityInstanceFields for EntityInstance {
+    fn field<T: LdtkFieldType>(&self, identifier: &str) -> Result<Option<T>, FieldError> {
+        let field_instance = match self
+            .field_instances
+            .iter()
+            .find(|f| f.identifier == identifier)
+        {
+            Some(field_instance) => field_instance,
+            None => return Ok(None),
+        };
+
+        T::from_field_value(&field_instance.value).ok_or_else(|| FieldError::WrongType {
+            identifier: identifier.to_string(),
+            expected: T::EXPECTED,
+            found: field_instance.field_instance_type.clone(),
+        })
+    }
+
+    fn required_field<T: LdtkFieldType>(&self, identifier: &str) -> Result<T, FieldError> {
+        if !self
+            .field_instances
+            .iter()
+            .any(|f| f.identifier == identifier)
+        {
+            return Err(FieldError::Missing {
+                identifier: identifier.to_string(),
+            });
+        }
+
+        self.field(identifier)?.ok_or_else(|| FieldError::Null {
+            identifier: identifier.to_string(),
+        })
+    }
+}
+
+/// Components built from the fields of an LDtk entity.
+pub trait LdtkFields: Sized {
+    fn from_ldtk_fields(entity_instance: &EntityInstance) -> Result<Self, FieldError>;
+}
+
+/// Implements `LdtkFields` and `From<EntityInstance>` for a component,
+/// so it can be used with `#[from_entity_instance]`.
+///
+/// Each member is read from the first of its field identifiers the entity has.
+/// Members whose fields are missing or null keep their value from `Default`,
+/// fields with the wrong type are logged and the whole component falls back to `Default`.
+///
+/// ```ignore
+/// impl_ldtk_fields!(EnemyAi {
+///     sight_range: "sight_range",
+///     chase_speed: "chase_speed" | "speed",
+/// });
+/// ```
+macro_rules! impl_ldtk_fields {
+    ($component:ty { $($member:tt: $($identifier:literal)|+),* $(,)? }) => {
+        impl $crate::ldtk_fields::LdtkFields for $component {
+            fn from_ldtk_fields(
+                entity_instance: &bevy_ecs_ldtk::prelude::EntityInstance,
+            ) -> Result<Self, $crate::ldtk_fields::FieldError> {
+                use $crate::ldtk_fields::EntityInstanceFields;
+
+                let mut component = <$component>::default();
+                $(
+                    for identifier in [$($identifier),+] {
+                        if let Some(value) = entity_instance.field(identifier)? {
+                            component.$member = value;
+                            break;
+                        }
+                    }
+                )*
+                Ok(component)
+            }
+        }
+
+        impl From<bevy_ecs_ldtk::prelude::EntityInstance> for $component {
+            fn from(entity_instance: bevy_ecs_ldtk::prelude::EntityInstance) -> Self {
+                <$component as $crate::ldtk_fields::LdtkFields>::from_ldtk_fields(&entity_instance)
+                    .unwrap_or_else(|e| {
+                        bevy::log::warn!(
+                            "Invalid {} entity {}: {e}",
+                            entity_instance.identifier,
+                            entity_instance.iid
+                        );
+                        <$component>::default()
+                    })
+            }
+        }
+    };
+}
+
+pub(crate) use impl_ldtk_fields;
+
+/// Implements `LdtkFieldType` for an enum without data, read from an LDtk enum field
+/// whose values are named like its variants.
+///
+/// ```ignore
+/// impl_ldtk_enum_field!(SwitchLogic { All, Any });
+/// ```
+macro_rules! impl_ldtk_enum_field {
+    ($enum:ident { $($variant:ident),+ $(,)? }) => {
+        impl $crate::ldtk_fields::LdtkFieldType for $enum {
+            const EXPECTED: &'static str = concat!(stringify!($enum), " enum");
+
+            fn from_field_value(
+                value: &bevy_ecs_ldtk::prelude::FieldValue,
+            ) -> Option<Option<Self>> {
+                match <String as $crate::ldtk_fields::LdtkFieldType>::from_field_value(value)? {
+                    Some(name) => match name.as_str() {
+                        $(stringify!($variant) => Some(Some($enum::$variant)),)+
+                        _ => None,
+                    },
+                    None => Some(None),
+                }
+            }
+        }
+    };
+}
+pub(crate) use impl_ldtk_enum_field;
@@ -0,0 +1,572 @@
+//! Choosing the LDtk project and the level the game starts in,
+//! from the command line or from the level menu in game.
+
+use crate::{
+    components::*,
+    doors::{DoorDestination, DoorTransition, DOOR_FADE_TIME},
+    hot_reload::PendingReload,
+    input::*,
+    resources::*,
+    save::PendingLoad,
+    speedrun::RunTimer,
+};
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_ecs_ldtk::{
+    ldtk::{LdtkJson, Level},
+    prelude::*,
+};
+use bevy_rapier2d::prelude::*;
+use thiserror::Error;
+
+use std::{ffi::OsStr, fmt, fs, path::PathBuf};
+
+pub const DEFAULT_PROJECT: &str = "Typical_2D_platformer_example.ldtk";
+
+pub const USAGE: &str = "\
+Usage: bevy_ldtk_example [--project FILE] [--level LEVEL] [--replay FILE] [--list]
+
+  --project FILE  LDtk project in assets to load, defaults to Typical_2D_platformer_example.ldtk
+  --level LEVEL   level to start in, by identifier, uid or iid, defaults to the first level
+  --replay FILE   play back a recorded run, like saves/replays/last_run.json,
+                  starting in its project and level
+  --list          print the levels of every project in assets and exit
+
+In game, the level menu (Tab by default) switches to another project or level.";
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum LevelSelectError {
+    #[error("couldn't read project {project}: {reason}")]
+    UnreadableProject { project: String, reason: String },
+    #[error("project {project} has no levels")]
+    NoLevels { project: String },
+    #[error("project {project} has no level {level}, its levels are: {available}")]
+    UnknownLevel {
+        project: String,
+        level: LevelRef,
+        available: String,
+    },
+    #[error("{flag} needs a value")]
+    MissingValue { flag: String },
+    #[error("unknown argument {0}")]
+    UnknownArgument(String),
+}
+
+/// A level of a project, as given on the command line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LevelRef {
+    Identifier(String),
+    Uid(i32),
+    Iid(String),
+}
+
+/// LDtk identifiers can't start with a digit or contain dashes,
+/// so numbers are read as uids and anything with a dash as an iid.
+impl From<&str> for LevelRef {
+    fn from(value: &str) -> Self {
+        if let Ok(uid) = value.parse() {
+            LevelRef::Uid(uid)
+        } else if value.contains('-') {
+            LevelRef::Iid(value.to_string())
+        } else {
+            LevelRef::Identifier(value.to_string())
+        }
+    }
+}
+
+impl fmt::Display for LevelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelRef::Identifier(identifier) => write!(f, "{identifier}"),
+            LevelRef::Uid(uid) => write!(f, "with uid {uid}"),
+            LevelRef::Iid(iid) => write!(f, "with iid {iid}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LevelInfo {
+    pub identifier: String,
+    pub uid: i32,
+    pub iid: String,
+    pub has_player: bool,
+    /// The first Checkpoint or Door of the level,
+    /// where the player is placed when starting in a level without a Player entity.
+    pub arrival: Option<String>,
+}
+
+impl LevelInfo {
+    fn new(level: &Level) -> Self {
+        let entity_instances: Vec<&EntityInstance> = level
+            .layer_instances
+            .iter()
+            .flatten()
+            .flat_map(|layer_instance| &layer_instance.entity_instances)
+            .collect();
+
+        let first_iid = |identifier: &str| {
+            entity_instances
+                .iter()
+                .find(|entity_instance| entity_instance.identifier == identifier)
+                .map(|entity_instance| entity_instance.iid.clone())
+        };
+
+        LevelInfo {
+            identifier: level.identifier.clone(),
+            uid: level.uid,
+            iid: level.iid.clone(),
+            has_player: first_iid("Player").is_some(),
+            arrival: first_iid("Checkpoint").or_else(|| first_iid("Door")),
+        }
+    }
+
+    pub fn matches(&self, level: &LevelRef) -> bool {
+        match level {
+            LevelRef::Identifier(identifier) => self.identifier == *identifier,
+            LevelRef::Uid(uid) => self.uid == *uid,
+            LevelRef::Iid(iid) => self.iid == *iid,
+        }
+    }
+}
+
+/// The levels of an LDtk project in `assets`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProjectInfo {
+    /// Path of the project relative to `assets`, as it's given to the `AssetServer`
+    pub file: String,
+    pub levels: Vec<LevelInfo>,
+}
+
+impl ProjectInfo {
+    pub fn assets_path() -> PathBuf {
+        FileAssetIo::get_base_path().join("assets")
+    }
+
+    /// The `.ldtk` files in `assets`, sorted by name.
+    pub fn project_files() -> Vec<String> {
+        let mut files: Vec<String> = match fs::read_dir(Self::assets_path()) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension() == Some(OsStr::new("ldtk")))
+                .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        files.sort();
+        files
+    }
+
+    /// Reads a project in `assets`, including its external level files.
+    pub fn load(file: &str) -> Result<Self, LevelSelectError> {
+        let unreadable = |reason: String| LevelSelectError::UnreadableProject {
+            project: file.to_string(),
+            reason,
+        };
+
+        let path = Self::assets_path().join(file);
+        let contents = fs::read_to_string(&path).map_err(|e| unreadable(e.to_string()))?;
+        let project: LdtkJson =
+            serde_json::from_str(&contents).map_err(|e| unreadable(e.to_string()))?;
+
+        let mut levels = Vec::new();
+        for level in project
+            .levels
+            .iter()
+            .chain(project.worlds.iter().flat_map(|world| world.levels.iter()))
+        {
+            match (&level.layer_instances, &level.external_rel_path) {
+                (None, Some(external_rel_path)) => {
+                    let external_path = path.parent().unwrap_or(&path).join(external_rel_path);
+                    let contents = fs::read_to_string(&external_path)
+                        .map_err(|e| unreadable(format!("{}: {e}", external_path.display())))?;
+                    let external_level: Level = serde_json::from_str(&contents)
+                        .map_err(|e| unreadable(format!("{}: {e}", external_path.display())))?;
+                    levels.push(LevelInfo::new(&external_level));
+                }
+                _ => levels.push(LevelInfo::new(level)),
+            }
+        }
+
+        if levels.is_empty() {
+            return Err(LevelSelectError::NoLevels {
+                project: file.to_string(),
+            });
+        }
+
+        Ok(ProjectInfo {
+            file: file.to_string(),
+            levels,
+        })
+    }
+
+    /// Finds the given level, or the first level if there's none.
+    pub fn find(&self, level: Option<&LevelRef>) -> Result<&LevelInfo, LevelSelectError> {
+        let level = match level {
+            Some(level) => level,
+            None => {
+                return self
+                    .levels
+                    .first()
+                    .ok_or_else(|| LevelSelectError::NoLevels {
+                        project: self.file.clone(),
+                    })
+            }
+        };
+
+        self.levels
+            .iter()
+            .find(|level_info| level_info.matches(level))
+            .ok_or_else(|| LevelSelectError::UnknownLevel {
+                project: self.file.clone(),
+                level: level.clone(),
+                available: self
+                    .levels
+                    .iter()
+                    .map(|level_info| format!("{} (uid {})", level_info.identifier, level_info.uid))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    }
+}
+
+/// The project currently loaded, relative to `assets`.
+#[derive(Clone, Debug, PartialEq, Eq, Resource)]
+pub struct CurrentProject(pub String);
+
+impl Default for CurrentProject {
+    fn default() -> Self {
+        CurrentProject(DEFAULT_PROJECT.to_string())
+    }
+}
+
+/// Loads `project` and starts in `level`, sent by the level menu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartLevel {
+    pub project: String,
+    pub level_selection: LevelSelection,
+    /// Moves the player into the level if its Player entity lives in another level
+    pub transition: DoorTransition,
+}
+
+impl StartLevel {
+    pub fn new(project: &ProjectInfo, level: &LevelInfo) -> Self {
+        let player_level = project.levels.iter().find(|level| level.has_player);
+
+        let (level_iid, transition) = match (&level.arrival, player_level) {
+            // The player spawns in its own level, so that one is selected first and the player
+            // goes through to this one once it has spawned, behind a faded out screen
+            (Some(entity_iid), Some(player_level)) if !level.has_player => (
+                player_level.iid.clone(),
+                DoorTransition::FadingOut {
+                    destination: DoorDestination {
+                        level_iid: level.iid.clone(),
+                        entity_iid: entity_iid.clone(),
+                    },
+                    elapsed: DOOR_FADE_TIME,
+                },
+            ),
+            (None, Some(_)) if !level.has_player => {
+                warn!(
+                    "Level {} has no Player, Checkpoint or Door to start at",
+                    level.identifier
+                );
+                (level.iid.clone(), DoorTransition::Idle)
+            }
+            _ => (level.iid.clone(), DoorTransition::Idle),
+        };
+
+        StartLevel {
+            project: project.file.clone(),
+            level_selection: LevelSelection::Iid(level_iid),
+            transition,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchOptions {
+    pub project: Option<String>,
+    pub level: Option<LevelRef>,
+    /// Replay file to play back
+    pub replay: Option<PathBuf>,
+    /// Print the levels of every project instead of starting the game
+    pub list: bool,
+}
+
+impl LaunchOptions {
+    /// Parses the command line arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, LevelSelectError> {
+        let mut options = LaunchOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next().ok_or_else(|| LevelSelectError::MissingValue {
+                    flag: flag.to_string(),
+                })
+            };
+
+            match arg.as_str() {
+                "--project" => options.project = Some(value(&arg)?),
+                "--level" => options.level = Some(LevelRef::from(value(&arg)?.as_str())),
+                "--replay" => options.replay = Some(PathBuf::from(value(&arg)?)),
+                "--list" => options.list = true,
+                _ => return Err(LevelSelectError::UnknownArgument(arg)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Checks that the project and level exist before starting the game.
+    pub fn start(&self) -> Result<StartLevel, LevelSelectError> {
+        let project = ProjectInfo::load(self.project.as_deref().unwrap_or(DEFAULT_PROJECT))?;
+        let level = project.find(self.level.as_ref())?;
+        Ok(StartLevel::new(&project, level))
+    }
+}
+
+/// Prints every project in `assets` with its levels, for `--list`.
+pub fn print_levels() {
+    for file in ProjectInfo::project_files() {
+        match ProjectInfo::load(&file) {
+            Ok(project) => {
+                println!("{file}");
+                for level in &project.levels {
+                    println!(
+                        "  {} (uid {}, iid {})",
+                        level.identifier, level.uid, level.iid
+                    );
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// State of the level menu, the projects are read again every time it opens.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct LevelMenu {
+    pub open: bool,
+    pub projects: Vec<ProjectInfo>,
+    /// Index into `entries`
+    pub selected: usize,
+}
+
+impl LevelMenu {
+    /// Every selectable level, as indices of the project and of the level in it.
+    pub fn entries(&self) -> Vec<(usize, usize)> {
+        self.projects
+            .iter()
+            .enumerate()
+            .flat_map(|(p, project)| (0..project.levels.len()).map(move |l| (p, l)))
+            .collect()
+    }
+}
+
+/// Marks the UI node listing the levels of the level menu.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LevelMenuUi;
+
+pub fn setup_level_menu(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.),
+                    top: Val::Px(8.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0., 0., 0., 0.8).into(),
+            z_index: ZIndex::Global(i32::MAX - 1),
+            ..Default::default()
+        },
+        LevelMenuUi,
+    ));
+}
+
+/// Opens and navigates the level menu.
+///
+/// Runs in `CoreStage::PreUpdate` after `update_action_state`,
+/// and consumes the actions while the menu is open so the player doesn't move.
+/// Physics are paused until the menu is closed.
+pub fn level_menu(
+    mut actions: ResMut<ActionState>,
+    mut menu: ResMut<LevelMenu>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut start_events: EventWriter<StartLevel>,
+    current_project: Res<CurrentProject>,
+    level_selection: Res<LevelSelection>,
+) {
+    if actions.just_pressed(Action::LevelMenu) {
+        if menu.open {
+            menu.open = false;
+        } else {
+            let projects: Vec<ProjectInfo> = ProjectInfo::project_files()
+                .iter()
+                .filter_map(|file| match ProjectInfo::load(file) {
+                    Ok(project) => Some(project),
+                    Err(e) => {
+                        warn!("{e}");
+                        None
+                    }
+                })
+                .collect();
+
+            *menu = LevelMenu {
+                open: true,
+                projects,
+                selected: 0,
+            };
+
+            // Start out on the level being played
+            let current_iid = match &*level_selection {
+                LevelSelection::Iid(iid) => Some(iid.as_str()),
+                _ => None,
+            };
+            menu.selected = menu
+                .entries()
+                .iter()
+                .position(|(p, l)| {
+                    let project = &menu.projects[*p];
+                    project.file == current_project.0
+                        && Some(project.levels[*l].iid.as_str()) == current_iid
+                })
+                .unwrap_or(0);
+        }
+
+        actions.consume();
+    } else if menu.open {
+        let entries = menu.entries();
+
+        if actions.axis_just_engaged(ActionAxis::Climb) && !entries.is_empty() {
+            menu.selected = if actions.axis(ActionAxis::Climb) > 0. {
+                (menu.selected + entries.len() - 1) % entries.len()
+            } else {
+                (menu.selected + 1) % entries.len()
+            };
+        }
+
+        if actions.just_pressed(Action::Jump) || actions.just_pressed(Action::Interact) {
+            if let Some((p, l)) = entries.get(menu.selected) {
+                let project = &menu.projects[*p];
+                start_events.send(StartLevel::new(project, &project.levels[*l]));
+            }
+            menu.open = false;
+        }
+
+        actions.consume();
+    }
+
+    if rapier_config.physics_pipeline_active == menu.open {
+        rapier_config.physics_pipeline_active = !menu.open;
+    }
+}
+
+pub fn update_level_menu_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    menu: Res<LevelMenu>,
+    mut menu_ui: Query<(Entity, &mut Style), With<LevelMenuUi>>,
+) {
+    if !menu.is_changed() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+    let entries = menu.entries();
+
+    for (ui_entity, mut style) in &mut menu_ui {
+        style.display = if menu.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+
+        commands.entity(ui_entity).despawn_descendants();
+        if !menu.open {
+            continue;
+        }
+
+        commands.entity(ui_entity).with_children(|ui| {
+            for (p, project) in menu.projects.iter().enumerate() {
+                ui.spawn(TextBundle::from_section(
+                    project.file.clone(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 14.,
+                        color: Color::GRAY,
+                    },
+                ));
+
+                for (l, level) in project.levels.iter().enumerate() {
+                    let selected = entries.get(menu.selected) == Some(&(p, l));
+                    ui.spawn(TextBundle::from_section(
+                        format!("{} {}", if selected { ">" } else { " " }, level.identifier),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.,
+                            color: if selected {
+                                Color::YELLOW
+                            } else {
+                                Color::WHITE
+                            },
+                        },
+                    ));
+                }
+            }
+        });
+    }
+}
+
+/// Replaces the loaded world with the project and level picked in the level menu,
+/// starting over without any progress.
+#[allow(clippy::too_many_arguments)]
+pub fn start_level(
+    mut commands: Commands,
+    mut start_events: EventReader<StartLevel>,
+    asset_server: Res<AssetServer>,
+    mut current_project: ResMut<CurrentProject>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut door_transition: ResMut<DoorTransition>,
+    mut progress: ResMut<WorldProgress>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    mut pending_load: ResMut<PendingLoad>,
+    mut pending_reload: ResMut<PendingReload>,
+    mut run_timer: ResMut<RunTimer>,
+    worlds: Query<Entity, With<Handle<LdtkAsset>>>,
+    mut cameras: Query<&mut CameraController>,
+) {
+    let start = match start_events.iter().last() {
+        Some(start) => start.clone(),
+        None => return,
+    };
+
+    for world_entity in &worlds {
+        commands.entity(world_entity).despawn_recursive();
+    }
+
+    commands.spawn(LdtkWorldBundle {
+        ldtk_handle: asset_server.load(start.project.as_str()),
+        ..Default::default()
+    });
+
+    current_project.0 = start.project;
+    *level_selection = start.level_selection;
+    *door_transition = start.transition;
+    *progress = WorldProgress::default();
+    *respawn_point = RespawnPoint::default();
+    pending_load.0 = None;
+    pending_reload.0 = None;
+    run_timer.reset();
+
+    for mut camera_controller in &mut cameras {
+        camera_controller.level_iid = None;
+        camera_controller.transition = None;
+    }
+}
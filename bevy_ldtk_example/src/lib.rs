@@ -0,0 +1,25 @@
+//! The game's components, resources and systems,
+//! shared by the game in `main.rs` and the tools in `src/bin`.
+
+pub mod animation;
+pub mod components;
+pub mod debug;
+pub mod dialogue;
+pub mod doors;
+pub mod game;
+pub mod hot_reload;
+pub mod input;
+pub mod inventory;
+pub mod ldtk_fields;
+pub mod level_select;
+pub mod minimap;
+pub mod platforms;
+pub mod registry;
+pub mod replay;
+pub mod resources;
+pub mod save;
+pub mod speedrun;
+pub mod switches;
+pub mod systems;
+pub mod tick;
+pub mod validation;
@@ -2,48 +2,63 @@
 // Please run with `--release`.
 
 use bevy::prelude::*;
-use bevy_ecs_ldtk::prelude::*;
 
-use bevy_rapier2d::prelude::*;
+use bevy_ldtk_example::{game::GamePlugin, level_select, replay};
 
-mod components;
-mod systems;
+use std::{env, process};
 
 fn main() {
+    let mut options = match level_select::LaunchOptions::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", level_select::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if options.list {
+        level_select::print_levels();
+        return;
+    }
+
+    // Replays start where they were recorded
+    let playback = match &options.replay {
+        Some(path) => match replay::Replay::read(path) {
+            Ok(replay) => {
+                options.project = Some(replay.project.clone());
+                options.level = Some(level_select::LevelRef::Iid(replay.level_iid.clone()));
+                replay::ReplayPlayback::new(&replay)
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                process::exit(2);
+            }
+        },
+        None => replay::ReplayPlayback::default(),
+    };
+
+    let start = match options.start() {
+        Ok(start) => start,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+
     App::new()
-        .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
-        .add_plugin(LdtkPlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
-        .insert_resource(RapierConfiguration {
-            gravity: Vec2::new(0.0, -2000.0),
-            ..Default::default()
-        })
-        .insert_resource(LevelSelection::Uid(0))
-        .insert_resource(LdtkSettings {
-            level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
-                load_level_neighbors: true,
-            },
-            set_clear_color: SetClearColor::FromLevelBackground,
-            ..Default::default()
-        })
-        .add_startup_system(systems::setup)
-        .add_system(systems::spawn_wall_collision)
-        .add_system(systems::movement)
-        .add_system(systems::detect_climb_range)
-        .add_system(systems::ignore_gravity_if_climbing)
-        .add_system(systems::patrol)
-        .add_system(systems::camera_fit_inside_current_level)
-        .add_system(systems::update_level_selection)
-        .add_system(systems::dbg_player_items)
-        .add_system(systems::spawn_ground_sensor)
-        .add_system(systems::ground_detection)
-        .add_system(systems::update_on_ground)
-        .add_system(systems::restart_level)
-        .register_ldtk_int_cell::<components::WallBundle>(1)
-        .register_ldtk_int_cell::<components::LadderBundle>(2)
-        .register_ldtk_int_cell::<components::WallBundle>(3)
-        .register_ldtk_entity::<components::PlayerBundle>("Player")
-        .register_ldtk_entity::<components::MobBundle>("Mob")
-        .register_ldtk_entity::<components::ChestBundle>("Chest")
+        .add_plugins(
+            DefaultPlugins
+                .set(ImagePlugin::default_nearest())
+                // Reload levels when the LDtk project is saved
+                .set(AssetPlugin {
+                    watch_for_changes: true,
+                    ..Default::default()
+                }),
+        )
+        .add_plugin(GamePlugin)
+        .insert_resource(level_select::CurrentProject(start.project))
+        .insert_resource(start.level_selection)
+        .insert_resource(start.transition)
+        .insert_resource(playback)
         .run();
 }
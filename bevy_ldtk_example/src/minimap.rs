@@ -0,0 +1,404 @@
+//! A minimap drawn from the IntGrid layers of every level in the world,
+//! which can be toggled to a full screen map.
+//!
+//! Levels only show up on the map once they've been selected,
+//! the discovered levels are saved with the rest of `WorldProgress`.
+
+use crate::{components::*, input::*, resources::*};
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_ecs_ldtk::{
+    ldtk::{Level, Type},
+    prelude::*,
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// Size of the box the minimap is fit into, in pixels.
+const MINIMAP_SIZE: Vec2 = Vec2::new(192., 128.);
+
+/// Distance of the minimap from the corner of the window, in pixels.
+const MINIMAP_MARGIN: f32 = 8.;
+
+/// How much of the window the full screen map can take up.
+const FULL_MAP_FRACTION: f32 = 0.9;
+
+/// Size of the player's marker, in pixels.
+const MINIMAP_PLAYER_SIZE: f32 = 4.;
+
+/// Drawn behind the IntGrid cells of discovered levels.
+const MINIMAP_LEVEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.15, 0.85);
+
+#[derive(Clone, Debug, Default, Resource)]
+pub struct Minimap {
+    pub full_screen: bool,
+    pub image: Handle<Image>,
+    /// Area of the world covered by the map, in LDtk world coordinates with y pointing down
+    pub bounds: Rect,
+    /// Area of each level by iid, in the same coordinates as `bounds`
+    pub levels: HashMap<String, Rect>,
+    /// The discovered levels the image was last drawn with, `None` until it's drawn
+    drawn: Option<HashSet<String>>,
+}
+
+/// Marks the node containing the map image and markers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct MinimapUi;
+
+/// Marks the node highlighting the selected level on the map.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct MinimapLevelMarker;
+
+/// Marks the node showing where the player is on the map.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct MinimapPlayerMarker;
+
+pub fn setup_minimap(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = images.add(Image::default());
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            MinimapUi,
+        ))
+        .with_children(|minimap| {
+            minimap.spawn(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    ..Default::default()
+                },
+                image: image.clone().into(),
+                ..Default::default()
+            });
+
+            minimap.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        ..Default::default()
+                    },
+                    background_color: Color::rgba(1., 1., 1., 0.2).into(),
+                    ..Default::default()
+                },
+                MinimapLevelMarker,
+            ));
+
+            minimap.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        size: Size::new(Val::Px(MINIMAP_PLAYER_SIZE), Val::Px(MINIMAP_PLAYER_SIZE)),
+                        ..Default::default()
+                    },
+                    background_color: Color::RED.into(),
+                    ..Default::default()
+                },
+                MinimapPlayerMarker,
+            ));
+        });
+
+    commands.insert_resource(Minimap {
+        image,
+        ..Default::default()
+    });
+}
+
+/// Discovers levels as they're selected, whether by walking into them, going through a door
+/// or loading a save.
+pub fn discover_levels(level_selection: Res<LevelSelection>, mut progress: ResMut<WorldProgress>) {
+    if !level_selection.is_changed() {
+        return;
+    }
+
+    // The game always selects levels by iid
+    if let LevelSelection::Iid(iid) = &*level_selection {
+        if !progress.discovered_levels.contains(iid) {
+            progress.discovered_levels.insert(iid.clone());
+        }
+    }
+}
+
+/// Fills the pixels of `image_data` covering `area`, given in map pixels.
+fn fill_area(image_data: &mut [u8], image_size: UVec2, area: Rect, color: Color) {
+    let min = area.min.floor().max(Vec2::ZERO).as_uvec2();
+    let max = area.max.ceil().as_uvec2().min(image_size);
+    let rgba = color.as_rgba_u32().to_le_bytes();
+
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            let i = ((y * image_size.x + x) * 4) as usize;
+            image_data[i..i + 4].copy_from_slice(&rgba);
+        }
+    }
+}
+
+/// Redraws the map image when the world is loaded or reloaded, and when levels are discovered.
+///
+/// Terrain edits aren't shown, the map is drawn from the level files.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_minimap(
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    mut ldtk_events: EventReader<AssetEvent<LdtkAsset>>,
+    mut level_events: EventReader<AssetEvent<LdtkLevel>>,
+    progress: Res<WorldProgress>,
+    worlds: Query<&Handle<LdtkAsset>>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+) {
+    let assets_changed = ldtk_events.iter().count() + level_events.iter().count() > 0;
+    if !assets_changed && minimap.drawn.as_ref() == Some(&progress.discovered_levels) {
+        return;
+    }
+
+    let ldtk_asset = match worlds
+        .get_single()
+        .ok()
+        .and_then(|handle| ldtk_assets.get(handle))
+    {
+        Some(ldtk_asset) => ldtk_asset,
+        None => return,
+    };
+    let project = &ldtk_asset.project;
+
+    let levels: Vec<&Level> = project
+        .levels
+        .iter()
+        .chain(project.worlds.iter().flat_map(|world| world.levels.iter()))
+        .collect();
+
+    let level_area = |level: &Level| {
+        Rect::new(
+            level.world_x as f32,
+            level.world_y as f32,
+            (level.world_x + level.px_wid) as f32,
+            (level.world_y + level.px_hei) as f32,
+        )
+    };
+
+    let bounds = match levels
+        .iter()
+        .map(|level| level_area(level))
+        .reduce(|a, b| a.union(b))
+    {
+        Some(bounds) => bounds,
+        None => return,
+    };
+
+    // One map pixel per cell of the finest IntGrid layer
+    let cell_size = project
+        .defs
+        .layers
+        .iter()
+        .filter(|layer| layer.purple_type == Type::IntGrid)
+        .map(|layer| layer.grid_size)
+        .min()
+        .unwrap_or(16)
+        .max(1) as f32;
+
+    let image_size = (bounds.size() / cell_size).ceil().max(Vec2::ONE).as_uvec2();
+    let mut image_data = vec![0; (image_size.x * image_size.y * 4) as usize];
+    let to_map = |area: Rect| Rect {
+        min: (area.min - bounds.min) / cell_size,
+        max: (area.max - bounds.min) / cell_size,
+    };
+
+    for level in levels
+        .iter()
+        .filter(|level| progress.discovered_levels.contains(&level.iid))
+    {
+        fill_area(
+            &mut image_data,
+            image_size,
+            to_map(level_area(level)),
+            MINIMAP_LEVEL_COLOR,
+        );
+
+        // External levels only have their layers in the level asset
+        let layer_instances = match &level.layer_instances {
+            Some(layer_instances) => layer_instances,
+            None => match ldtk_asset
+                .level_map
+                .get(&level.iid)
+                .and_then(|handle| ldtk_levels.get(handle))
+                .and_then(|ldtk_level| ldtk_level.level.layer_instances.as_ref())
+            {
+                Some(layer_instances) => layer_instances,
+                None => continue,
+            },
+        };
+
+        // Layers are listed from the top down
+        for layer_instance in layer_instances.iter().rev() {
+            if layer_instance.layer_instance_type != Type::IntGrid || layer_instance.c_wid <= 0 {
+                continue;
+            }
+
+            let colors: HashMap<i32, Color> = project
+                .defs
+                .layers
+                .iter()
+                .find(|layer| layer.uid == layer_instance.layer_def_uid)
+                .map(|layer| {
+                    layer
+                        .int_grid_values
+                        .iter()
+                        .map(|value| (value.value, value.color))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let grid_size = layer_instance.grid_size as f32;
+            for (i, value) in layer_instance.int_grid_csv.iter().enumerate() {
+                let color = match colors.get(value) {
+                    Some(color) => *color,
+                    None => continue,
+                };
+
+                let cell = IVec2::new(
+                    i as i32 % layer_instance.c_wid,
+                    i as i32 / layer_instance.c_wid,
+                );
+                let min = Vec2::new(level.world_x as f32, level.world_y as f32)
+                    + cell.as_vec2() * grid_size;
+
+                fill_area(
+                    &mut image_data,
+                    image_size,
+                    to_map(Rect {
+                        min,
+                        max: min + grid_size,
+                    }),
+                    color,
+                );
+            }
+        }
+    }
+
+    if let Some(image) = images.get_mut(&minimap.image) {
+        *image = Image::new(
+            Extent3d {
+                width: image_size.x,
+                height: image_size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            image_data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+    }
+
+    minimap.bounds = bounds;
+    minimap.levels = levels
+        .iter()
+        .map(|level| (level.iid.clone(), level_area(level)))
+        .collect();
+    minimap.drawn = Some(progress.discovered_levels.clone());
+}
+
+/// Toggles the full screen map, and places the map and its markers.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn update_minimap_ui(
+    actions: Res<ActionState>,
+    windows: Res<Windows>,
+    level_selection: Res<LevelSelection>,
+    mut minimap: ResMut<Minimap>,
+    mut minimap_ui: Query<
+        &mut Style,
+        (
+            With<MinimapUi>,
+            Without<MinimapLevelMarker>,
+            Without<MinimapPlayerMarker>,
+        ),
+    >,
+    mut level_markers: Query<&mut Style, (With<MinimapLevelMarker>, Without<MinimapPlayerMarker>)>,
+    mut player_markers: Query<&mut Style, With<MinimapPlayerMarker>>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+) {
+    if actions.just_pressed(Action::Map) {
+        minimap.full_screen = !minimap.full_screen;
+    }
+
+    let window_size = match windows.get_primary() {
+        Some(window) => Vec2::new(window.width(), window.height()),
+        None => return,
+    };
+
+    let bounds_size = minimap.bounds.size();
+    let drawn = minimap.drawn.is_some() && bounds_size.min_element() > 0.;
+
+    // UI pixels per LDtk pixel, fitting the whole world into the box
+    let box_size = if minimap.full_screen {
+        window_size * FULL_MAP_FRACTION
+    } else {
+        MINIMAP_SIZE
+    };
+    let scale = (box_size / bounds_size.max(Vec2::ONE)).min_element();
+    let map_size = bounds_size * scale;
+
+    let map_position = if minimap.full_screen {
+        (window_size - map_size) / 2.
+    } else {
+        window_size - map_size - MINIMAP_MARGIN
+    };
+
+    for mut style in &mut minimap_ui {
+        style.display = if drawn { Display::Flex } else { Display::None };
+        style.position = UiRect {
+            left: Val::Px(map_position.x),
+            top: Val::Px(map_position.y),
+            ..Default::default()
+        };
+        style.size = Size::new(Val::Px(map_size.x), Val::Px(map_size.y));
+    }
+
+    let level_area = match &*level_selection {
+        LevelSelection::Iid(iid) => minimap.levels.get(iid),
+        _ => None,
+    };
+    for mut style in &mut level_markers {
+        match level_area {
+            Some(area) => {
+                let min = (area.min - minimap.bounds.min) * scale;
+                let size = area.size() * scale;
+                style.display = Display::Flex;
+                style.position = UiRect {
+                    left: Val::Px(min.x),
+                    top: Val::Px(min.y),
+                    ..Default::default()
+                };
+                style.size = Size::new(Val::Px(size.x), Val::Px(size.y));
+            }
+            None => style.display = Display::None,
+        }
+    }
+
+    // The world's y axis points up while LDtk's points down
+    let player_position = player_query.get_single().ok().map(|transform| {
+        let translation = transform.translation();
+        Vec2::new(translation.x, -translation.y)
+    });
+    for mut style in &mut player_markers {
+        match player_position {
+            Some(position) => {
+                let center = (position - minimap.bounds.min) * scale;
+                style.display = Display::Flex;
+                style.position = UiRect {
+                    left: Val::Px(center.x - MINIMAP_PLAYER_SIZE / 2.),
+                    top: Val::Px(center.y - MINIMAP_PLAYER_SIZE / 2.),
+                    ..Default::default()
+                };
+            }
+            None => style.display = Display::None,
+        }
+    }
+}
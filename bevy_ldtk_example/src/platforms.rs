@@ -0,0 +1,221 @@
+use crate::{
+    components::*,
+    input::*,
+    ldtk_fields::{impl_ldtk_enum_field, impl_ldtk_fields},
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+/// Collision group of one-way platforms, removed from the player's filters to pass through them.
+pub const ONE_WAY_PLATFORM_GROUP: Group = Group::GROUP_3;
+
+/// How far the player's feet can sink into a one-way platform and still stand on it, in pixels.
+const ONE_WAY_TOLERANCE: f32 = 4.;
+
+/// How far above the player's head one-way platforms are let through, in pixels,
+/// so a jump doesn't reach one before it's ignored.
+const ONE_WAY_APPROACH_MARGIN: f32 = 16.;
+
+/// How much faster than its platform a rider can move up and still be standing on it,
+/// in pixels per second.
+const PLATFORM_RIDE_TOLERANCE: f32 = 10.;
+
+/// What starts a moving platform.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum PlatformTrigger {
+    /// Moves from the start
+    #[default]
+    Always,
+    /// Waits until something stands on it
+    Stand,
+}
+
+impl_ldtk_enum_field!(PlatformTrigger { Always, Stand });
+
+/// A platform following its `Patrol`, carrying whatever stands on it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Component)]
+pub struct MovingPlatform {
+    pub trigger: PlatformTrigger,
+    /// Whether the player can jump through it from below and drop through it by holding down
+    pub one_way: bool,
+    /// Set once the trigger has fired, platforms stay triggered
+    pub triggered: bool,
+}
+
+impl MovingPlatform {
+    pub fn moves(&self) -> bool {
+        self.trigger == PlatformTrigger::Always || self.triggered
+    }
+}
+
+impl_ldtk_fields!(MovingPlatform {
+    trigger: "platform_trigger",
+    one_way: "one_way",
+});
+
+#[derive(Clone, Default, Bundle)]
+pub struct MovingPlatformBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    #[bundle]
+    pub collider_bundle: ColliderBundle,
+    pub collision_groups: CollisionGroups,
+    pub patrol: Patrol,
+    pub moving_platform: MovingPlatform,
+    pub entity_iid: EntityIid,
+}
+
+// Platforms are resizable rectangles in LDtk, so they're built manually like doors
+impl LdtkEntity for MovingPlatformBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        tileset: Option<&Handle<Image>>,
+        tileset_definition: Option<&TilesetDefinition>,
+        asset_server: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) -> MovingPlatformBundle {
+        let moving_platform = MovingPlatform::from(entity_instance.clone());
+
+        let collision_groups = if moving_platform.one_way {
+            CollisionGroups::new(ONE_WAY_PLATFORM_GROUP, Group::ALL)
+        } else {
+            CollisionGroups::default()
+        };
+
+        MovingPlatformBundle {
+            sprite_bundle: rectangle_sprite(entity_instance),
+            collider_bundle: ColliderBundle::from(entity_instance.clone()),
+            collision_groups,
+            patrol: Patrol::bundle_entity(
+                entity_instance,
+                layer_instance,
+                tileset,
+                tileset_definition,
+                asset_server,
+                texture_atlases,
+            ),
+            moving_platform,
+            entity_iid: EntityIid::from(entity_instance.clone()),
+        }
+    }
+}
+
+/// Starts `PlatformTrigger::Stand` platforms once something with ground detection lands on them.
+pub fn trigger_platforms(
+    ground_sensors: Query<&GroundSensor, Changed<GroundSensor>>,
+    mut platforms: Query<&mut MovingPlatform>,
+) {
+    for sensor in &ground_sensors {
+        for entity in &sensor.intersecting_ground_entities {
+            if let Ok(mut platform) = platforms.get_mut(*entity) {
+                if !platform.triggered {
+                    platform.triggered = true;
+                }
+            }
+        }
+    }
+}
+
+/// Carries the player and chests standing on moving platforms.
+///
+/// Riders take on changes in the platform's velocity, so their movement relative to it is kept,
+/// and don't fall behind a platform moving down.
+/// When they leave the platform they keep its velocity, `movement` handles the rest.
+pub fn ride_platforms(
+    ground_sensors: Query<&GroundSensor>,
+    mut riders: Query<(&mut Velocity, &mut GroundDetection), Without<MovingPlatform>>,
+    platforms: Query<&Velocity, With<MovingPlatform>>,
+) {
+    for sensor in &ground_sensors {
+        let (mut velocity, mut ground_detection) =
+            match riders.get_mut(sensor.ground_detection_entity) {
+                Ok(rider) => rider,
+                Err(_) => continue,
+            };
+
+        // Moving up relative to the platform means jumping off or through it
+        let platform_velocity = sensor
+            .intersecting_ground_entities
+            .iter()
+            .find_map(|entity| platforms.get(*entity).ok())
+            .map(|platform_velocity| platform_velocity.linvel)
+            .filter(|platform_velocity| {
+                velocity.linvel.y <= platform_velocity.y + PLATFORM_RIDE_TOLERANCE
+            });
+
+        match platform_velocity {
+            Some(platform_velocity) => {
+                velocity.linvel.x += platform_velocity.x - ground_detection.ground_velocity.x;
+                velocity.linvel.y = velocity.linvel.y.max(platform_velocity.y);
+                ground_detection.ground_velocity = platform_velocity;
+            }
+            None => {
+                if ground_detection.ground_velocity != Vec2::ZERO {
+                    ground_detection.ground_velocity = Vec2::ZERO;
+                }
+            }
+        }
+    }
+}
+
+/// Lets the player through one-way platforms from below, and down through them while holding down.
+#[allow(clippy::type_complexity)]
+pub fn one_way_platforms(
+    actions: Res<ActionState>,
+    mut players: Query<
+        (&GlobalTransform, &Collider, &mut CollisionGroups),
+        (With<Player>, Without<MovingPlatform>),
+    >,
+    platforms: Query<(&GlobalTransform, &Collider, &MovingPlatform)>,
+) {
+    for (player_transform, player_collider, mut groups) in &mut players {
+        let player_position = player_transform.translation().truncate();
+        let player_half_size = player_collider
+            .as_cuboid()
+            .map_or(Vec2::ZERO, |cuboid| cuboid.half_extents());
+        let feet = player_position.y - player_half_size.y;
+        let head = player_position.y + player_half_size.y;
+
+        let mut standing = false;
+        let mut below = false;
+
+        for (platform_transform, platform_collider, platform) in &platforms {
+            if !platform.one_way {
+                continue;
+            }
+
+            let platform_position = platform_transform.translation().truncate();
+            let platform_half_size = platform_collider
+                .as_cuboid()
+                .map_or(Vec2::ZERO, |cuboid| cuboid.half_extents());
+            let top = platform_position.y + platform_half_size.y;
+            let bottom = platform_position.y - platform_half_size.y;
+
+            let overlaps_horizontally = (platform_position.x - player_position.x).abs()
+                < platform_half_size.x + player_half_size.x;
+            if !overlaps_horizontally
+                || top < feet - ONE_WAY_TOLERANCE
+                || bottom > head + ONE_WAY_APPROACH_MARGIN
+            {
+                continue;
+            }
+
+            if feet >= top - ONE_WAY_TOLERANCE {
+                standing = true;
+            } else {
+                below = true;
+            }
+        }
+
+        let dropping = standing && actions.axis(ActionAxis::Climb) < 0.;
+        let passes_through = dropping || (below && !standing);
+
+        let mut filters = groups.filters;
+        filters.set(ONE_WAY_PLATFORM_GROUP, !passes_through);
+        if groups.filters != filters {
+            groups.filters = filters;
+        }
+    }
+}
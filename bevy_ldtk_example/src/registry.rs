@@ -0,0 +1,165 @@
+//! The LDtk entities and IntGrid values the game has bundles for.
+//!
+//! `GamePlugin` registers the bundles from these tables and `ValidationRules::game` checks
+//! levels against them, so adding a bundle here is all it takes for both.
+
+use crate::{
+    components::*,
+    dialogue::{Npc, NpcBundle},
+    doors::{Door, DoorBundle},
+    platforms::{MovingPlatform, MovingPlatformBundle},
+    switches::{GateBundle, LeverBundle, PressurePlate, PressurePlateBundle, Switch, SwitchInputs},
+    validation::{check_fields, check_patrol, EntityCheck},
+};
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+
+pub struct EntityRegistration {
+    pub identifier: &'static str,
+    register: fn(&mut App, &str),
+    /// Checks of the fields the bundle reads, for `ValidationRules`
+    pub checks: &'static [EntityCheck],
+}
+
+pub struct IntCellRegistration {
+    pub value: i32,
+    register: fn(&mut App, i32),
+    insert: fn(&mut EntityCommands, IntGridCell, &LayerInstance),
+}
+
+fn register_entity<B: LdtkEntity + Bundle>(app: &mut App, identifier: &str) {
+    app.register_ldtk_entity::<B>(identifier);
+}
+
+fn register_int_cell<B: LdtkIntCell + Bundle>(app: &mut App, value: i32) {
+    app.register_ldtk_int_cell::<B>(value);
+}
+
+fn insert_int_cell<B: LdtkIntCell + Bundle>(
+    entity: &mut EntityCommands,
+    int_grid_cell: IntGridCell,
+    layer_instance: &LayerInstance,
+) {
+    entity.insert(B::bundle_int_cell(int_grid_cell, layer_instance));
+}
+
+pub const ENTITIES: [EntityRegistration; 10] = [
+    EntityRegistration {
+        identifier: "Player",
+        register: register_entity::<PlayerBundle>,
+        checks: &[check_fields::<Items>],
+    },
+    EntityRegistration {
+        identifier: "Mob",
+        register: register_entity::<MobBundle>,
+        checks: &[
+            check_fields::<EnemyAi>,
+            check_fields::<Patrol>,
+            check_patrol,
+        ],
+    },
+    EntityRegistration {
+        identifier: "Chest",
+        register: register_entity::<ChestBundle>,
+        checks: &[check_fields::<Items>],
+    },
+    EntityRegistration {
+        identifier: "Checkpoint",
+        register: register_entity::<CheckpointBundle>,
+        checks: &[],
+    },
+    EntityRegistration {
+        identifier: "Door",
+        register: register_entity::<DoorBundle>,
+        checks: &[check_fields::<Door>, check_fields::<SwitchInputs>],
+    },
+    EntityRegistration {
+        identifier: "MovingPlatform",
+        register: register_entity::<MovingPlatformBundle>,
+        checks: &[
+            check_fields::<MovingPlatform>,
+            check_fields::<Patrol>,
+            check_patrol,
+        ],
+    },
+    EntityRegistration {
+        identifier: "PressurePlate",
+        register: register_entity::<PressurePlateBundle>,
+        checks: &[check_fields::<PressurePlate>],
+    },
+    EntityRegistration {
+        identifier: "Lever",
+        register: register_entity::<LeverBundle>,
+        checks: &[check_fields::<Switch>],
+    },
+    EntityRegistration {
+        identifier: "Gate",
+        register: register_entity::<GateBundle>,
+        checks: &[check_fields::<SwitchInputs>],
+    },
+    EntityRegistration {
+        identifier: "Npc",
+        register: register_entity::<NpcBundle>,
+        checks: &[check_fields::<Npc>],
+    },
+];
+
+pub const INT_CELLS: [IntCellRegistration; 6] = [
+    IntCellRegistration {
+        value: DIRT,
+        register: register_int_cell::<WallBundle>,
+        insert: insert_int_cell::<WallBundle>,
+    },
+    IntCellRegistration {
+        value: LADDER,
+        register: register_int_cell::<LadderBundle>,
+        insert: insert_int_cell::<LadderBundle>,
+    },
+    IntCellRegistration {
+        value: STONE,
+        register: register_int_cell::<WallBundle>,
+        insert: insert_int_cell::<WallBundle>,
+    },
+    IntCellRegistration {
+        value: SPIKES,
+        register: register_int_cell::<HazardBundle>,
+        insert: insert_int_cell::<HazardBundle>,
+    },
+    IntCellRegistration {
+        value: WATER,
+        register: register_int_cell::<LiquidBundle>,
+        insert: insert_int_cell::<LiquidBundle>,
+    },
+    IntCellRegistration {
+        value: LAVA,
+        register: register_int_cell::<LavaBundle>,
+        insert: insert_int_cell::<LavaBundle>,
+    },
+];
+
+/// Registers every bundle in `ENTITIES` and `INT_CELLS` with bevy_ecs_ldtk.
+pub fn register_bundles(app: &mut App) {
+    for entity in &ENTITIES {
+        (entity.register)(app, entity.identifier);
+    }
+    for int_cell in &INT_CELLS {
+        (int_cell.register)(app, int_cell.value);
+    }
+}
+
+/// Inserts the bundle registered for an IntGrid value.
+///
+/// bevy_ecs_ldtk only applies registered bundles while spawning a level,
+/// so cells added at runtime need to go through here instead.
+pub fn insert_int_cell_bundle(
+    entity: &mut EntityCommands,
+    int_grid_cell: IntGridCell,
+    layer_instance: &LayerInstance,
+) {
+    if let Some(int_cell) = INT_CELLS
+        .iter()
+        .find(|int_cell| int_cell.value == int_grid_cell.value)
+    {
+        (int_cell.insert)(entity, int_grid_cell, layer_instance);
+    }
+}
@@ -0,0 +1,374 @@
+//! Records the actions of every tick of a run, to play them back later
+//! or to show a ghost following the previous run.
+//!
+//! The last run is written to `replays/last_run.json` in the `SaveDirectory`,
+//! `saves/replays/last_run.json` by default, which can be played back
+//! with `--replay` to reproduce a bug.
+
+use crate::{
+    components::*, input::*, level_select::CurrentProject, save::SaveDirectory, speedrun::RunTimer,
+    tick::TICK_RATE,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Bump this whenever `Replay` changes.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Opacity of the ghost.
+const GHOST_ALPHA: f32 = 0.4;
+
+/// How far the player can drift from the recorded position before a replay counts as desynced,
+/// in pixels.
+const DESYNC_DISTANCE: f32 = 1.;
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("couldn't access the replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid replay file: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("replay file version {0} isn't supported, expected version {REPLAY_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("replay recorded at {0} ticks per second, expected {TICK_RATE}")]
+    UnsupportedTickRate(u64),
+}
+
+/// The same input for a number of consecutive ticks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputRun {
+    pub ticks: u32,
+    #[serde(flatten)]
+    pub input: TickInput,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub tick_rate: u64,
+    /// The LDtk project the run was played in, relative to `assets`.
+    pub project: String,
+    /// The level the run started in.
+    pub level_iid: String,
+    pub inputs: Vec<InputRun>,
+    /// The player's position at the end of every tick.
+    pub positions: Vec<Vec2>,
+}
+
+impl Replay {
+    pub fn new(project: String, level_iid: String) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            tick_rate: TICK_RATE,
+            project,
+            level_iid,
+            inputs: Vec::new(),
+            positions: Vec::new(),
+        }
+    }
+
+    pub fn last_run_path(saves: &Path) -> PathBuf {
+        saves.join("replays").join("last_run.json")
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), ReplayError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, ReplayError> {
+        let replay: Replay = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version));
+        }
+        if replay.tick_rate != TICK_RATE {
+            return Err(ReplayError::UnsupportedTickRate(replay.tick_rate));
+        }
+
+        Ok(replay)
+    }
+
+    /// Adds a tick to the end of the replay.
+    pub fn push(&mut self, input: TickInput, position: Vec2) {
+        match self.inputs.last_mut() {
+            Some(run) if run.input == input => run.ticks += 1,
+            _ => self.inputs.push(InputRun { ticks: 1, input }),
+        }
+        self.positions.push(position);
+    }
+
+    /// The input of every tick, in order.
+    pub fn tick_inputs(&self) -> impl Iterator<Item = &TickInput> {
+        self.inputs
+            .iter()
+            .flat_map(|run| (0..run.ticks).map(move |_| &run.input))
+    }
+}
+
+/// Records the current run, from when the `RunTimer` starts until it's reset.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ReplayRecorder {
+    pub replay: Option<Replay>,
+    /// Splits of the run when the replay was last written
+    written_splits: usize,
+}
+
+/// Plays back the inputs of a replay given with `--replay` instead of the player's,
+/// handing control back to the player once it ends.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ReplayPlayback {
+    inputs: Vec<TickInput>,
+    positions: Vec<Vec2>,
+    tick: usize,
+    actions: ActionState,
+    desynced: bool,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: &Replay) -> Self {
+        ReplayPlayback {
+            inputs: replay.tick_inputs().cloned().collect(),
+            positions: replay.positions.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.tick < self.inputs.len()
+    }
+}
+
+/// The run the ghost follows, the previous run by default.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct GhostRun {
+    pub replay: Option<Replay>,
+    tick: usize,
+}
+
+impl GhostRun {
+    /// Follows the last run recorded, if there is one.
+    pub fn load_or_default(saves: &Path) -> Self {
+        let path = Replay::last_run_path(saves);
+        if !path.exists() {
+            return GhostRun::default();
+        }
+
+        match Replay::read(&path) {
+            Ok(replay) => GhostRun {
+                replay: Some(replay),
+                tick: 0,
+            },
+            Err(e) => {
+                warn!("Failed to load the last run from {}: {e}", path.display());
+                GhostRun::default()
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Ghost;
+
+fn write_replay(replay: &Replay, saves: &Path) {
+    let path = Replay::last_run_path(saves);
+    if let Err(e) = replay.write(&path) {
+        warn!("Failed to save the replay to {}: {e}", path.display());
+    }
+}
+
+/// Replaces the actions of the tick with the replay's.
+///
+/// Runs in `TickStage::First` after `begin_tick`.
+pub fn play_replay(
+    run_timer: Res<RunTimer>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut actions: ResMut<ActionState>,
+) {
+    // Ticks only count while the run is going, like in `record_replay`
+    if !playback.is_playing() || !run_timer.running {
+        return;
+    }
+
+    let tick = playback.tick;
+    let input = playback.inputs[tick].clone();
+    playback.actions.clear_just_pressed();
+    playback.actions.set_tick_input(&input);
+    *actions = playback.actions.clone();
+}
+
+/// Records the actions and the player's position every tick of the run.
+///
+/// Runs in `TickStage::Last`.
+#[allow(clippy::too_many_arguments)]
+pub fn record_replay(
+    run_timer: Res<RunTimer>,
+    current_project: Res<CurrentProject>,
+    save_directory: Res<SaveDirectory>,
+    level_selection: Res<LevelSelection>,
+    levels: Query<&Handle<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+    actions: Res<ActionState>,
+    players: Query<&GlobalTransform, With<Player>>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut ghost_run: ResMut<GhostRun>,
+) {
+    if !run_timer.running {
+        // The finished run is the one to beat next
+        if let Some(replay) = recorder.replay.take() {
+            write_replay(&replay, &save_directory.0);
+            ghost_run.replay = Some(replay);
+            recorder.written_splits = 0;
+        }
+        return;
+    }
+
+    let position = match players.get_single() {
+        Ok(transform) => transform.translation().truncate(),
+        Err(_) => match recorder.replay.as_ref().and_then(|r| r.positions.last()) {
+            Some(position) => *position,
+            None => return,
+        },
+    };
+
+    if recorder.replay.is_none() {
+        let level_iid = levels
+            .iter()
+            .filter_map(|handle| ldtk_levels.get(handle))
+            .find(|ldtk_level| level_selection.is_match(&0, &ldtk_level.level))
+            .map(|ldtk_level| ldtk_level.level.iid.clone());
+
+        match level_iid {
+            Some(level_iid) => {
+                recorder.replay = Some(Replay::new(current_project.0.clone(), level_iid))
+            }
+            None => return,
+        }
+    }
+
+    if let Some(replay) = &mut recorder.replay {
+        replay.push(actions.tick_input(), position);
+    }
+
+    // Keep the file up to date with every level finished, in case the game doesn't exit cleanly
+    if run_timer.splits.len() != recorder.written_splits {
+        recorder.written_splits = run_timer.splits.len();
+        if let Some(replay) = &recorder.replay {
+            write_replay(replay, &save_directory.0);
+        }
+    }
+}
+
+/// Warns once when the player ends up somewhere else than in the replay being played back.
+///
+/// Runs in `TickStage::Last`.
+pub fn check_replay_sync(
+    run_timer: Res<RunTimer>,
+    mut playback: ResMut<ReplayPlayback>,
+    players: Query<&GlobalTransform, With<Player>>,
+) {
+    if !playback.is_playing() || !run_timer.running {
+        return;
+    }
+
+    let tick = playback.tick;
+    playback.tick += 1;
+
+    if playback.desynced {
+        return;
+    }
+
+    if let (Ok(transform), Some(expected)) = (players.get_single(), playback.positions.get(tick)) {
+        let position = transform.translation().truncate();
+        if position.distance(*expected) > DESYNC_DISTANCE {
+            warn!("Replay desynced at tick {tick}: player at {position}, recorded at {expected}");
+            playback.desynced = true;
+        }
+    }
+
+    if !playback.is_playing() {
+        info!("Replay finished after {} ticks", playback.inputs.len());
+    }
+}
+
+/// Moves the ghost along the positions of the ghost run, while a run from the same level goes on.
+///
+/// Runs in `TickStage::Last` after `record_replay`.
+#[allow(clippy::too_many_arguments)]
+pub fn move_ghost(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    run_timer: Res<RunTimer>,
+    recorder: Res<ReplayRecorder>,
+    mut ghost_run: ResMut<GhostRun>,
+    players: Query<&GlobalTransform, With<Player>>,
+    mut ghosts: Query<(Entity, &mut Transform, &mut Sprite), With<Ghost>>,
+) {
+    let same_start = match (&recorder.replay, &ghost_run.replay) {
+        (Some(current), Some(ghost)) => {
+            current.project == ghost.project && current.level_iid == ghost.level_iid
+        }
+        _ => false,
+    };
+
+    let tick = ghost_run.tick;
+    let position = match &ghost_run.replay {
+        Some(ghost) if same_start && run_timer.running => ghost.positions.get(tick).copied(),
+        _ => None,
+    };
+
+    let position = match position {
+        Some(position) => position,
+        None => {
+            for (ghost_entity, _, _) in &ghosts {
+                commands.entity(ghost_entity).despawn_recursive();
+            }
+            if !run_timer.running {
+                ghost_run.tick = 0;
+            }
+            return;
+        }
+    };
+
+    ghost_run.tick += 1;
+
+    // Just behind the player
+    let z = match players.get_single() {
+        Ok(transform) => transform.translation().z - 0.1,
+        Err(_) => 0.,
+    };
+
+    match ghosts.get_single_mut() {
+        Ok((_, mut transform, mut sprite)) => {
+            let moved = position.x - transform.translation.x;
+            if moved != 0. {
+                sprite.flip_x = moved < 0.;
+            }
+            transform.translation = position.extend(z);
+        }
+        Err(_) => {
+            commands.spawn((
+                SpriteBundle {
+                    texture: asset_server.load("player.png"),
+                    sprite: Sprite {
+                        color: Color::rgba(1., 1., 1., GHOST_ALPHA),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(position.extend(z)),
+                    ..Default::default()
+                },
+                Ghost,
+            ));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::components::LiquidKind;
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use std::{collections::HashSet, fs, path::Path};
+
+/// Reads a JSON file, falling back to the default if it's missing or invalid.
+///
+/// Invalid files are logged as `what`, missing ones are expected, like before the first save.
+pub fn load_json_or_default<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+    if !path.exists() {
+        return T::default();
+    }
+
+    let loaded = fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| Ok(serde_json::from_str(&contents)?));
+    match loaded {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("Failed to load {what} from {}: {e}", path.display());
+            T::default()
+        }
+    }
+}
+
+/// Tuning for the player's movement.
+///
+/// Speeds are in pixels per second, accelerations in pixels per second squared,
+/// and times in seconds.
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub struct PlayerMovementConfig {
+    pub run_speed: f32,
+    pub ground_acceleration: f32,
+    pub ground_deceleration: f32,
+    pub air_acceleration: f32,
+    pub air_deceleration: f32,
+    pub climb_speed: f32,
+    /// How quickly a climber is pulled to the center of the ladder, per second.
+    pub ladder_snap_rate: f32,
+    pub jump_speed: f32,
+    /// Multiplies the upward velocity when jump is released early, for variable jump height.
+    pub jump_cut: f32,
+    /// How long after walking off a ledge the player can still jump.
+    pub coyote_time: f32,
+    /// How long a jump pressed before landing is remembered.
+    pub jump_buffer_time: f32,
+    /// Maximum falling speed while pushing against a wall.
+    pub wall_slide_speed: f32,
+    pub wall_jump_velocity: Vec2,
+    /// How long horizontal input is ignored after a wall jump,
+    /// so the player can't immediately steer back into the wall.
+    pub wall_jump_control_lock: f32,
+}
+
+impl Default for PlayerMovementConfig {
+    fn default() -> Self {
+        Self {
+            run_speed: 200.,
+            ground_acceleration: 2000.,
+            ground_deceleration: 2500.,
+            air_acceleration: 1200.,
+            air_deceleration: 600.,
+            climb_speed: 200.,
+            ladder_snap_rate: 20.,
+            jump_speed: 500.,
+            jump_cut: 0.5,
+            coyote_time: 0.1,
+            jump_buffer_time: 0.1,
+            wall_slide_speed: 100.,
+            wall_jump_velocity: Vec2::new(250., 450.),
+            wall_jump_control_lock: 0.15,
+        }
+    }
+}
+
+/// Tuning for damage, death and stomping mobs.
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub struct CombatConfig {
+    /// Health lost when touching a mob
+    pub contact_damage: i32,
+    /// Velocity the player is knocked away from a mob with, in pixels per second
+    pub knockback: Vec2,
+    /// How long horizontal input is ignored after being knocked back, in seconds
+    pub knockback_control_lock: f32,
+    pub invincibility_time: f32,
+    /// Upward velocity after stomping a mob, in pixels per second
+    pub stomp_bounce: f32,
+    /// Health lost when touching spikes
+    pub hazard_damage: i32,
+    /// Upward velocity after touching spikes, in pixels per second
+    pub hazard_bounce: f32,
+    /// How long the death sequence lasts before respawning, in seconds
+    pub death_time: f32,
+}
+
+impl Default for CombatConfig {
+    fn default() -> Self {
+        Self {
+            contact_damage: 1,
+            knockback: Vec2::new(250., 300.),
+            knockback_control_lock: 0.25,
+            invincibility_time: 1.,
+            stomp_bounce: 350.,
+            hazard_damage: 1,
+            hazard_bounce: 450.,
+            death_time: 1.,
+        }
+    }
+}
+
+/// How a liquid affects the bodies in it, scaled by how much of them is submerged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiquidProperties {
+    /// Bodies with a lower density float up
+    pub density: f32,
+    /// Multiplies gravity while fully submerged
+    pub gravity_scale: f32,
+    /// Fraction of velocity lost per second while fully submerged
+    pub drag: f32,
+    /// Fastest the player swims, in pixels per second
+    pub swim_speed: f32,
+    /// Upward velocity of a swim stroke, in pixels per second
+    pub stroke_speed: f32,
+}
+
+/// Tuning for liquids and swimming.
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub struct LiquidConfig {
+    pub water: LiquidProperties,
+    pub lava: LiquidProperties,
+    /// How much of the player needs to be submerged to swim, from 0 to 1
+    pub swim_depth: f32,
+}
+
+impl LiquidConfig {
+    pub fn properties(&self, kind: LiquidKind) -> &LiquidProperties {
+        match kind {
+            LiquidKind::Water => &self.water,
+            LiquidKind::Lava => &self.lava,
+        }
+    }
+}
+
+impl Default for LiquidConfig {
+    fn default() -> Self {
+        Self {
+            water: LiquidProperties {
+                density: 1.2,
+                gravity_scale: 0.4,
+                drag: 3.,
+                swim_speed: 120.,
+                stroke_speed: 250.,
+            },
+            lava: LiquidProperties {
+                density: 3.,
+                gravity_scale: 0.3,
+                drag: 8.,
+                swim_speed: 40.,
+                stroke_speed: 100.,
+            },
+            swim_depth: 0.4,
+        }
+    }
+}
+
+/// Where the player respawns after dying.
+///
+/// Starts out as the player's spawn point and moves to the last checkpoint the player touched.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct RespawnPoint {
+    /// Relative to the player's parent, like its `Transform`
+    pub position: Option<Vec2>,
+    /// Iid of the checkpoint the position belongs to, lit up while it's active
+    pub checkpoint: Option<String>,
+}
+
+/// The lever, door or chest the interact action is used on this tick,
+/// so a single press never uses several of them at once.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Resource)]
+pub struct InteractTarget(pub Option<Entity>);
+
+/// Iids of LDtk entities the player has changed,
+/// so the changes survive level respawns and can be saved.
+#[derive(Clone, Debug, Default, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldProgress {
+    pub opened_chests: HashSet<String>,
+    pub defeated_mobs: HashSet<String>,
+    /// Shown dimly lit, unlike checkpoints the player hasn't reached yet
+    pub reached_checkpoints: HashSet<String>,
+    /// Levers switched from how they start out in LDtk
+    pub toggled_levers: HashSet<String>,
+    /// Levels shown on the minimap
+    pub discovered_levels: HashSet<String>,
+}
+
+/// Tuning for the camera following the player.
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub struct CameraConfig {
+    /// Half size of the area the player can move around in without moving the camera, in pixels
+    pub dead_zone: Vec2,
+    /// How quickly the camera catches up with the player, higher is faster
+    pub smoothing: f32,
+    /// How far ahead of the player the camera looks in the direction it's moving, in pixels
+    pub look_ahead: f32,
+    /// How quickly the look ahead switches sides, higher is faster
+    pub look_ahead_smoothing: f32,
+    /// How long the camera takes to move over to a newly selected level, in seconds
+    pub level_transition_time: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            dead_zone: Vec2::new(16., 24.),
+            smoothing: 6.,
+            look_ahead: 48.,
+            look_ahead_smoothing: 3.,
+            level_transition_time: 0.6,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{components::*, input::*, resources::*};
+use bevy::{asset::FileAssetIo, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Bump this whenever `SaveData` changes, and migrate older saves in `SaveData::read`.
+pub const SAVE_VERSION: u32 = 2;
+
+pub const SAVE_SLOTS: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("couldn't access the save file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid save file: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("save file version {0} is newer than the supported version {SAVE_VERSION}")]
+    UnsupportedVersion(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u32,
+    pub level_iid: String,
+    /// The player's translation, relative to its parent.
+    pub player_position: Vec2,
+    pub items: Vec<String>,
+    /// The player's current health, `None` in version 1 saves
+    #[serde(default)]
+    pub health: Option<i32>,
+    pub respawn_point: Option<Vec2>,
+    /// `None` in version 1 saves
+    #[serde(default)]
+    pub respawn_checkpoint: Option<String>,
+    pub progress: WorldProgress,
+}
+
+impl SaveData {
+    pub fn slot_path(saves: &Path, slot: u32) -> PathBuf {
+        saves.join(format!("slot_{slot}.json"))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0) as u32;
+        if version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+
+        // Version 2 only added optional fields, which version 1 saves deserialize without.
+        // Older versions would otherwise be upgraded to the current layout here
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// Where save slots, best times and replays are written.
+#[derive(Clone, Debug, PartialEq, Eq, Resource)]
+pub struct SaveDirectory(pub PathBuf);
+
+impl Default for SaveDirectory {
+    fn default() -> Self {
+        SaveDirectory(FileAssetIo::get_base_path().join("saves"))
+    }
+}
+
+/// The slot used by the save and load actions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Resource)]
+pub struct SaveSlot(pub u32);
+
+impl Default for SaveSlot {
+    fn default() -> Self {
+        SaveSlot(1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SaveGame {
+    pub slot: u32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LoadGame {
+    pub slot: u32,
+}
+
+/// A loaded save waiting for the player to exist before it's applied.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct PendingLoad(pub Option<SaveData>);
+
+pub fn save_actions(
+    actions: Res<ActionState>,
+    mut save_slot: ResMut<SaveSlot>,
+    mut save_events: EventWriter<SaveGame>,
+    mut load_events: EventWriter<LoadGame>,
+) {
+    if actions.just_pressed(Action::NextSaveSlot) {
+        save_slot.0 = save_slot.0 % SAVE_SLOTS + 1;
+        info!("Selected save slot {}", save_slot.0);
+    }
+
+    if actions.just_pressed(Action::Save) {
+        save_events.send(SaveGame { slot: save_slot.0 });
+    }
+
+    if actions.just_pressed(Action::Load) {
+        load_events.send(LoadGame { slot: save_slot.0 });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn save_game(
+    mut save_events: EventReader<SaveGame>,
+    save_directory: Res<SaveDirectory>,
+    level_selection: Res<LevelSelection>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+    player_query: Query<(&Transform, &Items, &Health), With<Player>>,
+    respawn_point: Res<RespawnPoint>,
+    progress: Res<WorldProgress>,
+) {
+    for SaveGame { slot } in save_events.iter() {
+        let level_iid = level_query
+            .iter()
+            .filter_map(|handle| ldtk_levels.get(handle))
+            .find(|ldtk_level| level_selection.is_match(&0, &ldtk_level.level))
+            .map(|ldtk_level| ldtk_level.level.iid.clone());
+
+        let (level_iid, (player_transform, items, health)) =
+            match (level_iid, player_query.get_single()) {
+                (Some(level_iid), Ok(player)) => (level_iid, player),
+                _ => {
+                    warn!("Nothing to save yet");
+                    continue;
+                }
+            };
+
+        let save_data = SaveData {
+            version: SAVE_VERSION,
+            level_iid,
+            player_position: player_transform.translation.truncate(),
+            items: items.iter().cloned().collect(),
+            health: Some(health.current),
+            respawn_point: respawn_point.position,
+            respawn_checkpoint: respawn_point.checkpoint.clone(),
+            progress: progress.clone(),
+        };
+
+        let path = SaveData::slot_path(&save_directory.0, *slot);
+        match save_data.write(&path) {
+            Ok(()) => info!("Saved to {}", path.display()),
+            Err(e) => warn!("Failed to save to {}: {e}", path.display()),
+        }
+    }
+}
+
+pub fn load_game(
+    mut load_events: EventReader<LoadGame>,
+    save_directory: Res<SaveDirectory>,
+    mut pending_load: ResMut<PendingLoad>,
+) {
+    for LoadGame { slot } in load_events.iter() {
+        let path = SaveData::slot_path(&save_directory.0, *slot);
+        match SaveData::read(&path) {
+            Ok(save_data) => pending_load.0 = Some(save_data),
+            Err(e) => warn!("Failed to load {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Moves the player into the saved level and respawns levels,
+/// so `apply_world_progress` reconciles the chests and mobs in them.
+#[allow(clippy::type_complexity)]
+pub fn apply_pending_load(
+    mut commands: Commands,
+    mut pending_load: ResMut<PendingLoad>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut progress: ResMut<WorldProgress>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &mut Items,
+            &mut Health,
+            &mut MovementState,
+            &mut TextureAtlasSprite,
+            &mut Visibility,
+        ),
+        With<Player>,
+    >,
+    level_query: Query<Entity, With<Handle<LdtkLevel>>>,
+) {
+    if pending_load.0.is_none() {
+        return;
+    }
+
+    let (
+        player_entity,
+        mut transform,
+        mut velocity,
+        mut items,
+        mut health,
+        mut state,
+        mut sprite,
+        mut visibility,
+    ) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    if let Some(save_data) = pending_load.0.take() {
+        *level_selection = LevelSelection::Iid(save_data.level_iid);
+        *progress = save_data.progress;
+        *respawn_point = RespawnPoint {
+            position: save_data.respawn_point,
+            checkpoint: save_data.respawn_checkpoint,
+        };
+
+        transform.translation.x = save_data.player_position.x;
+        transform.translation.y = save_data.player_position.y;
+        velocity.linvel = Vec2::ZERO;
+        *items = Items::new(save_data.items);
+
+        // Saved while dying, or before health was saved, the player comes back at full health
+        health.current = match save_data.health {
+            Some(current) if current > 0 => current.min(health.max),
+            _ => health.max,
+        };
+        health.invincible_for = 0.;
+
+        // Undo whatever the death sequence and invincibility did to the player
+        commands.entity(player_entity).remove::<Dying>();
+        *state = MovementState::default();
+        sprite.color = Color::WHITE;
+        visibility.is_visible = true;
+
+        for level_entity in &level_query {
+            commands.entity(level_entity).insert(Respawn);
+        }
+    }
+}
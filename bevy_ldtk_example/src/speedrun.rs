@@ -0,0 +1,292 @@
+//! A run timer with a split every time the player moves on to another level,
+//! compared against the best times saved to `best_splits.json` in the `SaveDirectory`.
+
+use crate::{
+    components::*, resources::load_json_or_default, save::SaveDirectory, tick::TICK_SECONDS,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The time a level was left at, measured from the start of the run in seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Split {
+    pub level_iid: String,
+    pub level_identifier: String,
+    pub time: f32,
+    /// Time spent in the level
+    pub segment: f32,
+    /// Difference to the best time the level was first left at, negative when ahead.
+    /// Only the first split of a level in a run is compared.
+    pub delta: Option<f32>,
+}
+
+/// Times of the current run, started when the player spawns.
+///
+/// The timer stops while physics are paused, and starts over when the level is restarted.
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct RunTimer {
+    pub running: bool,
+    pub elapsed: f32,
+    pub splits: Vec<Split>,
+    /// Iid and identifier of the level being played
+    current_level: Option<(String, String)>,
+    level_entered: f32,
+}
+
+impl RunTimer {
+    /// Discards the current run, a new one starts once the player spawns again.
+    pub fn reset(&mut self) {
+        *self = RunTimer::default();
+    }
+
+    fn start(&mut self) {
+        *self = RunTimer {
+            running: true,
+            ..Default::default()
+        };
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BestTimes {
+    pub level_identifier: String,
+    /// Earliest time the level was first left at in a run
+    pub split: Option<f32>,
+    /// Shortest time spent in the level
+    pub segment: Option<f32>,
+}
+
+/// Best times keyed by level iid, saved whenever a run improves on them.
+#[derive(Clone, Debug, Default, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BestSplits {
+    pub levels: HashMap<String, BestTimes>,
+}
+
+impl BestSplits {
+    pub fn path(saves: &Path) -> PathBuf {
+        saves.join("best_splits.json")
+    }
+
+    /// Loads the best times, starting without any if there are none yet or they're invalid.
+    pub fn load_or_default(saves: &Path) -> Self {
+        load_json_or_default(&Self::path(saves), "best splits")
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Compares a split to the best times, returning its delta and whether it improved on them.
+    fn record(&mut self, split: &Split, first_visit: bool) -> (Option<f32>, bool) {
+        let best = self.levels.entry(split.level_iid.clone()).or_default();
+        best.level_identifier = split.level_identifier.clone();
+
+        let mut improved = false;
+        let faster_segment = match best.segment {
+            Some(segment) => split.segment < segment,
+            None => true,
+        };
+        if faster_segment {
+            best.segment = Some(split.segment);
+            improved = true;
+        }
+
+        if !first_visit {
+            return (None, improved);
+        }
+
+        let delta = best.split.map(|time| split.time - time);
+        let faster_split = match delta {
+            Some(delta) => delta < 0.,
+            None => true,
+        };
+        if faster_split {
+            best.split = Some(split.time);
+            improved = true;
+        }
+
+        (delta, improved)
+    }
+}
+
+/// Formats seconds as minutes, seconds and hundredths.
+pub fn format_time(seconds: f32) -> String {
+    let hundredths = (seconds.abs() * 100.).round() as u32;
+    format!(
+        "{}:{:02}.{:02}",
+        hundredths / 6000,
+        hundredths / 100 % 60,
+        hundredths % 100
+    )
+}
+
+/// Marks the text showing the time of the run.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct RunTimeText;
+
+/// Marks the text showing the last split and its delta.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LastSplitText;
+
+pub fn setup_run_timer(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load("fonts/DejaVuSans.ttf"),
+        font_size: 14.,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Percent(45.),
+                    top: Val::Px(8.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                padding: UiRect::all(Val::Px(4.)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..Default::default()
+        })
+        .with_children(|ui| {
+            ui.spawn((TextBundle::from_section("", style.clone()), RunTimeText));
+            ui.spawn((TextBundle::from_section("", style), LastSplitText));
+        });
+}
+
+/// Starts the run once the player first spawns.
+pub fn start_run_timer(mut run_timer: ResMut<RunTimer>, players: Query<(), Added<Player>>) {
+    if !run_timer.running && !players.is_empty() {
+        run_timer.start();
+    }
+}
+
+/// Counts the ticks of the run, so replaying it takes the same time.
+pub fn tick_run_timer(mut run_timer: ResMut<RunTimer>) {
+    if run_timer.running {
+        run_timer.elapsed += TICK_SECONDS;
+    }
+}
+
+/// Records a split when the selected level changes, saving any new best times.
+pub fn record_splits(
+    level_selection: Res<LevelSelection>,
+    levels: Query<&Handle<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+    save_directory: Res<SaveDirectory>,
+    mut run_timer: ResMut<RunTimer>,
+    mut best_splits: ResMut<BestSplits>,
+) {
+    if !run_timer.running {
+        return;
+    }
+
+    let level = levels
+        .iter()
+        .filter_map(|handle| ldtk_levels.get(handle))
+        .find(|ldtk_level| level_selection.is_match(&0, &ldtk_level.level))
+        .map(|ldtk_level| {
+            (
+                ldtk_level.level.iid.clone(),
+                ldtk_level.level.identifier.clone(),
+            )
+        });
+
+    let level = match level {
+        Some(level) => level,
+        // Still spawning
+        None => return,
+    };
+
+    let (level_iid, level_identifier) = match &run_timer.current_level {
+        Some(current_level) if *current_level == level => return,
+        Some(current_level) => current_level.clone(),
+        None => {
+            run_timer.current_level = Some(level);
+            run_timer.level_entered = run_timer.elapsed;
+            return;
+        }
+    };
+
+    let first_visit = !run_timer
+        .splits
+        .iter()
+        .any(|split| split.level_iid == level_iid);
+
+    let mut split = Split {
+        level_iid,
+        level_identifier,
+        time: run_timer.elapsed,
+        segment: run_timer.elapsed - run_timer.level_entered,
+        delta: None,
+    };
+
+    let (delta, improved) = best_splits.record(&split, first_visit);
+    split.delta = delta;
+
+    if improved {
+        let path = BestSplits::path(&save_directory.0);
+        if let Err(e) = best_splits.write(&path) {
+            warn!("Failed to save best splits to {}: {e}", path.display());
+        }
+    }
+
+    run_timer.splits.push(split);
+    run_timer.current_level = Some(level);
+    run_timer.level_entered = run_timer.elapsed;
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_run_timer_ui(
+    run_timer: Res<RunTimer>,
+    mut time_texts: Query<&mut Text, (With<RunTimeText>, Without<LastSplitText>)>,
+    mut split_texts: Query<&mut Text, With<LastSplitText>>,
+) {
+    if !run_timer.is_changed() {
+        return;
+    }
+
+    for mut text in &mut time_texts {
+        text.sections[0].value = format_time(run_timer.elapsed);
+    }
+
+    let (label, color) = match run_timer.splits.last() {
+        Some(split) => {
+            let (delta, color) = match split.delta {
+                Some(delta) if delta < 0. => (format!(" -{}", format_time(delta)), Color::GREEN),
+                Some(delta) => (format!(" +{}", format_time(delta)), Color::RED),
+                None => (String::new(), Color::GRAY),
+            };
+            let label = format!(
+                "{} {}{delta}",
+                split.level_identifier,
+                format_time(split.time)
+            );
+            (label, color)
+        }
+        None => (String::new(), Color::WHITE),
+    };
+
+    for mut text in &mut split_texts {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+            text.sections[0].style.color = color;
+        }
+    }
+}
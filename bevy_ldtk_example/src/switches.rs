@@ -0,0 +1,291 @@
+//! Pressure plates and levers, and the gates and doors they open.
+//!
+//! Gates and doors list the switches they listen to in an `inputs` array of entity references.
+//! They open when all of them are on, or any of them with `logic` set to `Any`.
+//! Switches in levels that aren't spawned count as off.
+
+use crate::{
+    components::*,
+    ldtk_fields::{impl_ldtk_enum_field, impl_ldtk_fields},
+    resources::{InteractTarget, WorldProgress},
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::FieldInstanceEntityReference, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use std::collections::HashMap;
+
+/// How far outside of a lever the player can still pull it, in pixels.
+pub const LEVER_INTERACT_MARGIN: f32 = 8.;
+
+/// Opacity of switches that are off.
+const SWITCH_OFF_ALPHA: f32 = 0.4;
+
+/// Opacity of open gates.
+const OPEN_GATE_ALPHA: f32 = 0.2;
+
+/// On or off, for the pressure plates and levers gates and doors listen to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Switch {
+    pub on: bool,
+}
+
+// Only levers start out on, plates are pressed by whatever rests on them
+impl_ldtk_fields!(Switch { on: "on" });
+
+/// A switch that's on while something heavy enough rests on it.
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct PressurePlate {
+    /// Total mass needed to press the plate, as computed by rapier.
+    /// The player weighs about 0.03 and chests about 0.38.
+    pub required_mass: f32,
+}
+
+impl_ldtk_fields!(PressurePlate {
+    required_mass: "required_mass",
+});
+
+/// A switch the player toggles by interacting with it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Lever;
+
+/// How the inputs of a gate or door are combined.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum SwitchLogic {
+    /// Every input has to be on
+    #[default]
+    All,
+    /// One input being on is enough
+    Any,
+}
+
+impl_ldtk_enum_field!(SwitchLogic { All, Any });
+
+/// The switches a gate or door listens to.
+#[derive(Clone, Debug, Default, PartialEq, Component)]
+pub struct SwitchInputs {
+    pub inputs: Vec<FieldInstanceEntityReference>,
+    pub logic: SwitchLogic,
+    /// Powered while the inputs are off instead
+    pub inverted: bool,
+    /// Kept up to date by `update_switch_inputs`
+    pub powered: bool,
+}
+
+impl_ldtk_fields!(SwitchInputs {
+    inputs: "inputs",
+    logic: "logic",
+    inverted: "inverted",
+});
+
+impl SwitchInputs {
+    /// Whether a door can be used, doors without inputs only depend on being locked.
+    pub fn allows(&self) -> bool {
+        self.inputs.is_empty() || self.powered
+    }
+
+    fn evaluate(&self, switches: &HashMap<&str, bool>) -> bool {
+        let is_on = |input: &FieldInstanceEntityReference| {
+            switches
+                .get(input.entity_iid.as_str())
+                .copied()
+                .unwrap_or(false)
+        };
+
+        let satisfied = !self.inputs.is_empty()
+            && match self.logic {
+                SwitchLogic::All => self.inputs.iter().all(is_on),
+                SwitchLogic::Any => self.inputs.iter().any(is_on),
+            };
+
+        satisfied != self.inverted
+    }
+}
+
+/// A solid block that lets everything through while its inputs are powered.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Gate;
+
+#[derive(Clone, Default, Bundle)]
+pub struct PressurePlateBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub switch: Switch,
+    pub pressure_plate: PressurePlate,
+    pub entity_iid: EntityIid,
+}
+
+impl LdtkEntity for PressurePlateBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> PressurePlateBundle {
+        PressurePlateBundle {
+            sprite_bundle: rectangle_sprite(entity_instance),
+            switch: Switch::default(),
+            pressure_plate: PressurePlate::from(entity_instance.clone()),
+            entity_iid: EntityIid::from(entity_instance.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Default, Bundle)]
+pub struct LeverBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub switch: Switch,
+    pub lever: Lever,
+    pub entity_iid: EntityIid,
+}
+
+impl LdtkEntity for LeverBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> LeverBundle {
+        LeverBundle {
+            sprite_bundle: rectangle_sprite(entity_instance),
+            switch: Switch::from(entity_instance.clone()),
+            lever: Lever,
+            entity_iid: EntityIid::from(entity_instance.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Default, Bundle)]
+pub struct GateBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    #[bundle]
+    pub collider_bundle: ColliderBundle,
+    pub collision_groups: CollisionGroups,
+    pub gate: Gate,
+    pub switch_inputs: SwitchInputs,
+    pub entity_iid: EntityIid,
+}
+
+impl LdtkEntity for GateBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> GateBundle {
+        GateBundle {
+            sprite_bundle: rectangle_sprite(entity_instance),
+            collider_bundle: ColliderBundle::from(entity_instance.clone()),
+            collision_groups: CollisionGroups::default(),
+            gate: Gate,
+            switch_inputs: SwitchInputs::from(entity_instance.clone()),
+            entity_iid: EntityIid::from(entity_instance.clone()),
+        }
+    }
+}
+
+/// Turns pressure plates on while the bodies resting on them are heavy enough.
+pub fn press_plates(
+    rapier_context: Res<RapierContext>,
+    mut plates: Query<(&GlobalTransform, &Sprite, &PressurePlate, &mut Switch)>,
+    masses: Query<&ReadMassProperties>,
+) {
+    for (transform, sprite, plate, mut switch) in &mut plates {
+        let half_size = sprite.custom_size.unwrap_or_default() / 2.;
+        let shape = Collider::cuboid(half_size.x, half_size.y);
+
+        let mut pressed = false;
+        let mut mass = 0.;
+        rapier_context.intersections_with_shape(
+            transform.translation().truncate(),
+            0.,
+            &shape,
+            QueryFilter::only_dynamic().exclude_sensors(),
+            |entity| {
+                if let Ok(mass_properties) = masses.get(entity) {
+                    pressed = true;
+                    mass += mass_properties.0.mass;
+                }
+                true
+            },
+        );
+
+        let on = pressed && mass >= plate.required_mass;
+        if switch.on != on {
+            switch.on = on;
+        }
+    }
+}
+
+/// Toggles the lever the player interacts with, remembering it in `WorldProgress`.
+pub fn use_levers(
+    interact_target: Res<InteractTarget>,
+    mut progress: ResMut<WorldProgress>,
+    mut levers: Query<(&EntityIid, &mut Switch), With<Lever>>,
+) {
+    if let Some((iid, mut switch)) = interact_target
+        .0
+        .and_then(|lever| levers.get_mut(lever).ok())
+    {
+        switch.on = !switch.on;
+
+        // Levers are stored as toggled from how they start out in LDtk
+        if !progress.toggled_levers.remove(&iid.0) {
+            progress.toggled_levers.insert(iid.0.clone());
+        }
+    }
+}
+
+/// Powers gates and doors from the switches they listen to.
+pub fn update_switch_inputs(
+    switches: Query<(&EntityIid, &Switch)>,
+    mut switch_inputs: Query<&mut SwitchInputs>,
+) {
+    let switches: HashMap<&str, bool> = switches
+        .iter()
+        .map(|(iid, switch)| (iid.0.as_str(), switch.on))
+        .collect();
+
+    for mut switch_inputs in &mut switch_inputs {
+        let powered = switch_inputs.evaluate(&switches);
+        if switch_inputs.powered != powered {
+            switch_inputs.powered = powered;
+        }
+    }
+}
+
+/// Opens powered gates by letting everything pass through them.
+#[allow(clippy::type_complexity)]
+pub fn update_gates(
+    mut gates: Query<
+        (&SwitchInputs, &mut CollisionGroups, &mut Sprite),
+        (Changed<SwitchInputs>, With<Gate>),
+    >,
+) {
+    for (switch_inputs, mut groups, mut sprite) in &mut gates {
+        if switch_inputs.powered {
+            groups.filters = Group::NONE;
+            sprite.color.set_a(OPEN_GATE_ALPHA);
+        } else {
+            groups.filters = Group::ALL;
+            sprite.color.set_a(1.);
+        }
+    }
+}
+
+/// Dims switches that are off.
+pub fn update_switch_sprites(mut switches: Query<(&Switch, &mut Sprite), Changed<Switch>>) {
+    for (switch, mut sprite) in &mut switches {
+        sprite
+            .color
+            .set_a(if switch.on { 1. } else { SWITCH_OFF_ALPHA });
+    }
+}
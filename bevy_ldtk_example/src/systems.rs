@@ -1,62 +1,263 @@
-use crate::components::*;
+use crate::{
+    components::*,
+    debug::DebugOverlay,
+    dialogue::ActiveDialogue,
+    doors::{Door, DoorTransition, DOOR_INTERACT_MARGIN},
+    input::*,
+    inventory::CHEST_INTERACT_RANGE,
+    level_select::CurrentProject,
+    platforms::MovingPlatform,
+    registry::insert_int_cell_bundle,
+    resources::*,
+    speedrun::RunTimer,
+    switches::{Lever, Switch, LEVER_INTERACT_MARGIN},
+    tick::{TICK, TICK_SECONDS},
+};
 use bevy::prelude::*;
-use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_ldtk::{
+    prelude::*,
+    utils::{grid_coords_to_translation, translation_to_grid_coords},
+};
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage, TileVisible};
 
 use std::collections::{HashMap, HashSet};
 
 use bevy_rapier2d::prelude::*;
 
-pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    current_project: Res<CurrentProject>,
+) {
     let camera = Camera2dBundle::default();
-    commands.spawn(camera);
+    commands.spawn((camera, CameraController::default()));
 
-    let ldtk_handle = asset_server.load("Typical_2D_platformer_example.ldtk");
+    let ldtk_handle = asset_server.load(current_project.0.as_str());
     commands.spawn(LdtkWorldBundle {
         ldtk_handle,
         ..Default::default()
     });
 }
 
-pub fn dbg_player_items(
-    input: Res<Input<KeyCode>>,
-    mut query: Query<(&Items, &EntityInstance), With<Player>>,
-) {
-    for (items, entity_instance) in &mut query {
-        if input.just_pressed(KeyCode::P) {
-            dbg!(&items);
-            dbg!(&entity_instance);
-        }
+/// Moves `current` towards `target` by at most `max_delta`.
+fn approach(current: f32, target: f32, max_delta: f32) -> f32 {
+    if (target - current).abs() <= max_delta {
+        target
+    } else {
+        current + (target - current).signum() * max_delta
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn movement(
-    input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Velocity, &mut Climber, &GroundDetection), With<Player>>,
+    actions: Res<ActionState>,
+    config: Res<PlayerMovementConfig>,
+    dialogue: Res<ActiveDialogue>,
+    mut query: Query<
+        (
+            &mut Velocity,
+            &mut Climber,
+            &GroundDetection,
+            &WallDetection,
+            &mut MovementState,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
 ) {
-    for (mut velocity, mut climber, ground_detection) in &mut query {
-        let right = if input.pressed(KeyCode::D) { 1. } else { 0. };
-        let left = if input.pressed(KeyCode::A) { 1. } else { 0. };
+    let delta = TICK_SECONDS;
+
+    for (mut velocity, mut climber, ground_detection, wall_detection, mut state) in &mut query {
+        // Stand still while talking, the player has no friction to stop them otherwise
+        if dialogue.is_active() {
+            velocity.linvel.x = ground_detection.ground_velocity.x;
+            if climber.climbing {
+                velocity.linvel.y = 0.;
+            }
+            continue;
+        }
 
-        velocity.linvel.x = (right - left) * 200.;
+        let horizontal = actions.axis(ActionAxis::Move);
 
         if climber.intersecting_climbables.is_empty() {
             climber.climbing = false;
-        } else if input.just_pressed(KeyCode::W) || input.just_pressed(KeyCode::S) {
+        } else if actions.axis_just_engaged(ActionAxis::Climb) {
             climber.climbing = true;
+        } else if climber.climbing && ground_detection.on_ground && horizontal != 0. {
+            // Walk off the bottom of a ladder
+            climber.climbing = false;
+        }
+
+        if ground_detection.on_ground || climber.climbing {
+            state.time_since_grounded = 0.;
+        } else {
+            state.time_since_grounded += delta;
+        }
+
+        if actions.just_pressed(Action::Jump) {
+            state.jump_buffer = config.jump_buffer_time;
+        } else {
+            state.jump_buffer = (state.jump_buffer - delta).max(0.);
+        }
+
+        state.control_lock = (state.control_lock - delta).max(0.);
+
+        // Horizontal movement accelerates towards the target speed,
+        // and decelerates when there's no input or when turning around
+        if state.control_lock <= 0. {
+            let (acceleration, deceleration) = if ground_detection.on_ground {
+                (config.ground_acceleration, config.ground_deceleration)
+            } else {
+                (config.air_acceleration, config.air_deceleration)
+            };
+
+            // Relative to the platform being stood on, if any
+            let base = ground_detection.ground_velocity.x;
+            let relative = velocity.linvel.x - base;
+
+            let speeding_up =
+                horizontal != 0. && (relative == 0. || horizontal.signum() == relative.signum());
+            let rate = if speeding_up {
+                acceleration
+            } else {
+                deceleration
+            };
+
+            velocity.linvel.x =
+                base + approach(relative, horizontal * config.run_speed, rate * delta);
         }
 
         if climber.climbing {
-            let up = if input.pressed(KeyCode::W) { 1. } else { 0. };
-            let down = if input.pressed(KeyCode::S) { 1. } else { 0. };
+            velocity.linvel.y = actions.axis(ActionAxis::Climb) * config.climb_speed;
+        }
+
+        let wall_side = if wall_detection.on_wall_left {
+            -1.
+        } else if wall_detection.on_wall_right {
+            1.
+        } else {
+            0.
+        };
+        let airborne = !ground_detection.on_ground && !climber.climbing;
 
-            velocity.linvel.y = (up - down) * 200.;
+        // Slide down walls slowly while pushing against them
+        if airborne && wall_side != 0. && horizontal != 0. && horizontal.signum() == wall_side {
+            velocity.linvel.y = velocity.linvel.y.max(-config.wall_slide_speed);
         }
 
-        if input.just_pressed(KeyCode::Space) && (ground_detection.on_ground || climber.climbing) {
-            velocity.linvel.y = 500.;
-            climber.climbing = false;
+        if state.jump_buffer > 0. {
+            if state.time_since_grounded <= config.coyote_time {
+                velocity.linvel.y = config.jump_speed;
+                climber.climbing = false;
+                state.jumping = true;
+                state.jump_buffer = 0.;
+                // no more coyote jumps until the player is grounded again
+                state.time_since_grounded = f32::INFINITY;
+            } else if airborne && wall_side != 0. {
+                velocity.linvel = Vec2::new(
+                    -wall_side * config.wall_jump_velocity.x,
+                    config.wall_jump_velocity.y,
+                );
+                state.jumping = true;
+                state.jump_buffer = 0.;
+                state.control_lock = config.wall_jump_control_lock;
+            }
+        }
+
+        // Releasing jump early cuts the jump short
+        if state.jumping {
+            if velocity.linvel.y <= 0. {
+                state.jumping = false;
+            } else if !actions.pressed(Action::Jump) {
+                velocity.linvel.y *= config.jump_cut;
+                state.jumping = false;
+            }
+        }
+    }
+}
+
+/// Represents a wide wall that is 1 tile tall
+/// Used to spawn wall collisions
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct Plate {
+    left: i32,
+    right: i32,
+}
+
+/// A simple rectangle type representing a wall of any size
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct WallRect {
+    left: i32,
+    right: i32,
+    top: i32,
+    bottom: i32,
+}
+
+/// Combines the wall tiles of a single level into as few rectangles as possible.
+fn merge_wall_rects(level_walls: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<WallRect> {
+    // combine wall tiles into flat "plates" in each individual row
+    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
+
+    for y in 0..height {
+        let mut row_plates: Vec<Plate> = Vec::new();
+        let mut plate_start = None;
+
+        // + 1 to the width so the algorithm "terminates" plates that touch the right
+        // edge
+        for x in 0..width + 1 {
+            match (plate_start, level_walls.contains(&GridCoords { x, y })) {
+                (Some(s), false) => {
+                    row_plates.push(Plate {
+                        left: s,
+                        right: x - 1,
+                    });
+                    plate_start = None;
+                }
+                (None, true) => plate_start = Some(x),
+                _ => (),
+            }
+        }
+
+        plate_stack.push(row_plates);
+    }
+
+    // combine "plates" into rectangles across multiple rows
+    let mut wall_rects: Vec<WallRect> = Vec::new();
+    let mut previous_rects: HashMap<Plate, WallRect> = HashMap::new();
+
+    // an extra empty row so the algorithm "terminates" the rects that touch the top
+    // edge
+    plate_stack.push(Vec::new());
+
+    for (y, row) in plate_stack.iter().enumerate() {
+        let mut current_rects: HashMap<Plate, WallRect> = HashMap::new();
+        for plate in row {
+            if let Some(previous_rect) = previous_rects.remove(plate) {
+                current_rects.insert(
+                    *plate,
+                    WallRect {
+                        top: previous_rect.top + 1,
+                        ..previous_rect
+                    },
+                );
+            } else {
+                current_rects.insert(
+                    *plate,
+                    WallRect {
+                        bottom: y as i32,
+                        top: y as i32,
+                        left: plate.left,
+                        right: plate.right,
+                    },
+                );
+            }
         }
+
+        // Any plates that weren't removed above have terminated
+        wall_rects.append(&mut previous_rects.values().copied().collect());
+        previous_rects = current_rects;
     }
+
+    wall_rects
 }
 
 /// Spawns heron collisions for the walls of a level
@@ -75,28 +276,30 @@ pub fn movement(
 /// 2. combine wall tiles into flat "plates" in each individual row
 /// 3. combine the plates into rectangles across multiple rows wherever possible
 /// 4. spawn colliders for each rectangle
+///
+/// Levels are rebuilt when they gain new walls or are flagged with `RebuildWallCollision`,
+/// which replaces only that level's colliders.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_wall_collision(
     mut commands: Commands,
-    wall_query: Query<(&GridCoords, &Parent), Added<Wall>>,
+    wall_query: Query<(&GridCoords, &Parent), With<Wall>>,
+    new_wall_query: Query<&Parent, Added<Wall>>,
     parent_query: Query<&Parent, Without<Wall>>,
     level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+    rebuild_query: Query<Entity, With<RebuildWallCollision>>,
+    wall_collider_query: Query<(Entity, &Parent), With<WallCollider>>,
     levels: Res<Assets<LdtkLevel>>,
 ) {
-    /// Represents a wide wall that is 1 tile tall
-    /// Used to spawn wall collisions
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
-    struct Plate {
-        left: i32,
-        right: i32,
-    }
+    // An intgrid tile's direct parent will be a layer entity, not the level entity
+    // To get the level entity, you need the tile's grandparent.
+    // This is where parent_query comes in.
+    let level_of = |parent: &Parent| parent_query.get(parent.get()).ok().map(Parent::get);
+
+    let mut dirty_levels: HashSet<Entity> = new_wall_query.iter().filter_map(level_of).collect();
+    dirty_levels.extend(rebuild_query.iter());
 
-    /// A simple rectangle type representing a wall of any size
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
-    struct Rect {
-        left: i32,
-        right: i32,
-        top: i32,
-        bottom: i32,
+    if dirty_levels.is_empty() {
+        return;
     }
 
     // Consider where the walls are
@@ -109,131 +312,583 @@ pub fn spawn_wall_collision(
     let mut level_to_wall_locations: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
 
     wall_query.for_each(|(&grid_coords, parent)| {
-        // An intgrid tile's direct parent will be a layer entity, not the level entity
-        // To get the level entity, you need the tile's grandparent.
-        // This is where parent_query comes in.
-        if let Ok(grandparent) = parent_query.get(parent.get()) {
+        if let Some(level) = level_of(parent).filter(|level| dirty_levels.contains(level)) {
             level_to_wall_locations
-                .entry(grandparent.get())
+                .entry(level)
                 .or_insert(HashSet::new())
                 .insert(grid_coords);
         }
     });
 
-    if !wall_query.is_empty() {
-        level_query.for_each(|(level_entity, level_handle)| {
-            if let Some(level_walls) = level_to_wall_locations.get(&level_entity) {
-                let level = levels
-                    .get(level_handle)
-                    .expect("Level should be loaded by this point");
-
-                let LayerInstance {
-                    c_wid: width,
-                    c_hei: height,
-                    grid_size,
-                    ..
-                } = level
-                    .level
-                    .layer_instances
-                    .clone()
-                    .expect("Level asset should have layers")[0];
-
-                // combine wall tiles into flat "plates" in each individual row
-                let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
-
-                for y in 0..height {
-                    let mut row_plates: Vec<Plate> = Vec::new();
-                    let mut plate_start = None;
-
-                    // + 1 to the width so the algorithm "terminates" plates that touch the right
-                    // edge
-                    for x in 0..width + 1 {
-                        match (plate_start, level_walls.contains(&GridCoords { x, y })) {
-                            (Some(s), false) => {
-                                row_plates.push(Plate {
-                                    left: s,
-                                    right: x - 1,
-                                });
-                                plate_start = None;
-                            }
-                            (None, true) => plate_start = Some(x),
-                            _ => (),
-                        }
+    // Stale colliders are replaced as a whole, the rectangles of a level can change shape
+    // arbitrarily when a single tile is added or removed.
+    for (collider_entity, parent) in &wall_collider_query {
+        if dirty_levels.contains(&parent.get()) {
+            commands.entity(collider_entity).despawn_recursive();
+        }
+    }
+
+    for (level_entity, level_handle) in &level_query {
+        if !dirty_levels.contains(&level_entity) {
+            continue;
+        }
+
+        commands
+            .entity(level_entity)
+            .remove::<RebuildWallCollision>();
+
+        let level_walls = match level_to_wall_locations.get(&level_entity) {
+            Some(level_walls) => level_walls,
+            None => continue,
+        };
+
+        let layer_instance = match levels
+            .get(level_handle)
+            .and_then(|level| level.level.layer_instances.as_ref())
+            .and_then(|layer_instances| layer_instances.first())
+        {
+            Some(layer_instance) => layer_instance,
+            None => {
+                error!("Can't build wall collision for a level without layers");
+                continue;
+            }
+        };
+
+        let LayerInstance {
+            c_wid: width,
+            c_hei: height,
+            grid_size,
+            ..
+        } = *layer_instance;
+
+        let wall_rects = merge_wall_rects(level_walls, width, height);
+
+        commands.entity(level_entity).with_children(|level| {
+            // Spawn colliders for every rectangle..
+            // Making the collider a child of the level serves two purposes:
+            // 1. Adjusts the transforms to be relative to the level for free
+            // 2. the colliders will be despawned automatically when levels unload
+            for wall_rect in wall_rects {
+                level
+                    .spawn_empty()
+                    .insert(Collider::cuboid(
+                        (wall_rect.right as f32 - wall_rect.left as f32 + 1.) * grid_size as f32
+                            / 2.,
+                        (wall_rect.top as f32 - wall_rect.bottom as f32 + 1.) * grid_size as f32
+                            / 2.,
+                    ))
+                    .insert(RigidBody::Fixed)
+                    .insert(Friction::new(1.0))
+                    .insert(Transform::from_xyz(
+                        (wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32 / 2.,
+                        (wall_rect.bottom + wall_rect.top + 1) as f32 * grid_size as f32 / 2.,
+                        0.,
+                    ))
+                    .insert(GlobalTransform::default())
+                    .insert(WallCollider);
+            }
+        });
+    }
+}
+
+/// How far above its top a ladder can still be grabbed, so it can be climbed down from the top.
+const LADDER_GRAB_HEIGHT: f32 = 4.;
+
+/// Thickness of the solid top of a ladder.
+const LADDER_TOP_THICKNESS: f32 = 2.;
+
+/// Combines the ladder tiles of a single level into vertical columns, as (x, bottom, top).
+fn merge_ladder_columns(level_ladders: &HashSet<GridCoords>) -> Vec<(i32, i32, i32)> {
+    let mut ladders: Vec<GridCoords> = level_ladders.iter().copied().collect();
+    ladders.sort_by_key(|grid_coords| (grid_coords.x, grid_coords.y));
+
+    let mut columns: Vec<(i32, i32, i32)> = Vec::new();
+    for GridCoords { x, y } in ladders {
+        match columns.last_mut() {
+            Some((column_x, _, top)) if *column_x == x && *top + 1 == y => *top = y,
+            _ => columns.push((x, y, y)),
+        }
+    }
+
+    columns
+}
+
+/// Spawns a climbable sensor for every column of ladder tiles in a level,
+/// with a solid top that can be stood on.
+///
+/// Like `spawn_wall_collision`, this avoids a collider for every tile,
+/// and rebuilds a level when it gains ladders or is flagged with `RebuildWallCollision`.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn spawn_ladder_collision(
+    mut commands: Commands,
+    ladder_query: Query<(&GridCoords, &Parent), With<Ladder>>,
+    new_ladder_query: Query<&Parent, Added<Ladder>>,
+    parent_query: Query<&Parent, Without<Ladder>>,
+    level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+    rebuild_query: Query<Entity, With<RebuildWallCollision>>,
+    ladder_collider_query: Query<(Entity, &Parent), Or<(With<LadderColumn>, With<LadderTop>)>>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    // Like walls, a ladder tile's grandparent is its level
+    let level_of = |parent: &Parent| parent_query.get(parent.get()).ok().map(Parent::get);
+
+    let mut dirty_levels: HashSet<Entity> = new_ladder_query.iter().filter_map(level_of).collect();
+    dirty_levels.extend(rebuild_query.iter());
+
+    if dirty_levels.is_empty() {
+        return;
+    }
+
+    let mut level_to_ladder_locations: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
+    for (&grid_coords, parent) in &ladder_query {
+        if let Some(level) = level_of(parent).filter(|level| dirty_levels.contains(level)) {
+            level_to_ladder_locations
+                .entry(level)
+                .or_default()
+                .insert(grid_coords);
+        }
+    }
+
+    for (collider_entity, parent) in &ladder_collider_query {
+        if dirty_levels.contains(&parent.get()) {
+            commands.entity(collider_entity).despawn_recursive();
+        }
+    }
+
+    for (level_entity, level_handle) in &level_query {
+        let level_ladders = match level_to_ladder_locations.get(&level_entity) {
+            Some(level_ladders) => level_ladders,
+            None => continue,
+        };
+
+        let grid_size = match levels
+            .get(level_handle)
+            .and_then(|level| level.level.layer_instances.as_ref())
+            .and_then(|layer_instances| layer_instances.first())
+        {
+            Some(layer_instance) => layer_instance.grid_size as f32,
+            None => {
+                error!("Can't build ladder collision for a level without layers");
+                continue;
+            }
+        };
+
+        commands.entity(level_entity).with_children(|level| {
+            for (x, bottom, top) in merge_ladder_columns(level_ladders) {
+                let center_x = (x as f32 + 0.5) * grid_size;
+                let bottom = bottom as f32 * grid_size;
+                let top = (top + 1) as f32 * grid_size;
+                // The sensor reaches a little above the ladder
+                let half_height = (top - bottom + LADDER_GRAB_HEIGHT) / 2.;
+                let center_y = bottom + half_height;
+
+                level.spawn((
+                    SensorBundle {
+                        collider: Collider::cuboid(grid_size / 2., half_height),
+                        sensor: Sensor,
+                        active_events: ActiveEvents::COLLISION_EVENTS,
+                        rotation_constraints: LockedAxes::ROTATION_LOCKED,
+                    },
+                    Transform::from_xyz(center_x, center_y, 0.),
+                    GlobalTransform::default(),
+                    Climbable,
+                    LadderColumn {
+                        top: top - center_y,
+                    },
+                ));
+
+                level.spawn((
+                    Collider::cuboid(grid_size / 2., LADDER_TOP_THICKNESS / 2.),
+                    RigidBody::Fixed,
+                    CollisionGroups::new(LADDER_TOP_GROUP, Group::ALL),
+                    Transform::from_xyz(center_x, top - LADDER_TOP_THICKNESS / 2., 0.),
+                    GlobalTransform::default(),
+                    LadderTop,
+                ));
+            }
+        });
+    }
+}
+
+/// Z of terrain volumes relative to their level,
+/// in front of its layers so liquids tint whatever is in them.
+const TERRAIN_VOLUME_Z: f32 = 10.;
+
+/// Opacity of liquids.
+const LIQUID_ALPHA: f32 = 0.6;
+
+/// Spawns a sensor for every rectangle of hazard or liquid cells in a level,
+/// drawn in the color of their IntGrid value since the auto layers have no tiles for them.
+///
+/// Cells are merged like walls, separately for every IntGrid value.
+/// Like `spawn_ladder_collision`, levels are rebuilt when they gain such cells
+/// or are flagged with `RebuildWallCollision`.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn spawn_terrain_volumes(
+    mut commands: Commands,
+    cell_query: Query<
+        (
+            &GridCoords,
+            &Parent,
+            &IntGridCell,
+            Option<&Hazard>,
+            Option<&Liquid>,
+        ),
+        Or<(With<Hazard>, With<Liquid>)>,
+    >,
+    new_cell_query: Query<&Parent, (Or<(Added<Hazard>, Added<Liquid>)>, Without<TerrainVolume>)>,
+    parent_query: Query<&Parent, (Without<Hazard>, Without<Liquid>)>,
+    level_query: Query<(Entity, &Handle<LdtkLevel>, &Parent)>,
+    rebuild_query: Query<Entity, With<RebuildWallCollision>>,
+    volume_query: Query<(Entity, &Parent), With<TerrainVolume>>,
+    world_query: Query<&Handle<LdtkAsset>>,
+    levels: Res<Assets<LdtkLevel>>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+) {
+    // Like walls, a cell's grandparent is its level
+    let level_of = |parent: &Parent| parent_query.get(parent.get()).ok().map(Parent::get);
+
+    let mut dirty_levels: HashSet<Entity> = new_cell_query.iter().filter_map(level_of).collect();
+    dirty_levels.extend(rebuild_query.iter());
+
+    if dirty_levels.is_empty() {
+        return;
+    }
+
+    // Cells of a level by IntGrid value, along with what their volumes do
+    let mut level_to_cells: HashMap<
+        Entity,
+        HashMap<i32, (HashSet<GridCoords>, Option<Hazard>, Option<Liquid>)>,
+    > = HashMap::new();
+    for (&grid_coords, parent, int_grid_cell, hazard, liquid) in &cell_query {
+        if let Some(level) = level_of(parent).filter(|level| dirty_levels.contains(level)) {
+            level_to_cells
+                .entry(level)
+                .or_default()
+                .entry(int_grid_cell.value)
+                .or_insert_with(|| (HashSet::new(), hazard.copied(), liquid.copied()))
+                .0
+                .insert(grid_coords);
+        }
+    }
+
+    for (volume_entity, parent) in &volume_query {
+        if dirty_levels.contains(&parent.get()) {
+            commands.entity(volume_entity).despawn_recursive();
+        }
+    }
+
+    for (level_entity, level_handle, world_entity) in &level_query {
+        let level_cells = match level_to_cells.get(&level_entity) {
+            Some(level_cells) => level_cells,
+            None => continue,
+        };
+
+        let layer_instance = match levels.get(level_handle).and_then(|level| {
+            level
+                .level
+                .layer_instances
+                .iter()
+                .flatten()
+                .find(|layer_instance| !layer_instance.int_grid_csv.is_empty())
+        }) {
+            Some(layer_instance) => layer_instance,
+            None => {
+                error!("Can't build terrain volumes for a level without IntGrid layers");
+                continue;
+            }
+        };
+
+        let int_grid_values = world_query
+            .get(world_entity.get())
+            .ok()
+            .and_then(|handle| ldtk_assets.get(handle))
+            .and_then(|ldtk_asset| {
+                ldtk_asset
+                    .project
+                    .defs
+                    .layers
+                    .iter()
+                    .find(|layer| layer.uid == layer_instance.layer_def_uid)
+            })
+            .map(|layer| layer.int_grid_values.as_slice())
+            .unwrap_or_default();
+
+        let grid_size = layer_instance.grid_size as f32;
+
+        commands.entity(level_entity).with_children(|level| {
+            for (value, (cells, hazard, liquid)) in level_cells {
+                let mut color = int_grid_values
+                    .iter()
+                    .find(|int_grid_value| int_grid_value.value == *value)
+                    .map_or(Color::WHITE, |int_grid_value| int_grid_value.color);
+                if liquid.is_some() {
+                    color.set_a(LIQUID_ALPHA);
+                }
+
+                for rect in merge_wall_rects(cells, layer_instance.c_wid, layer_instance.c_hei) {
+                    let size = Vec2::new(
+                        (rect.right - rect.left + 1) as f32,
+                        (rect.top - rect.bottom + 1) as f32,
+                    ) * grid_size;
+
+                    let mut volume = level.spawn((
+                        SensorBundle {
+                            collider: Collider::cuboid(size.x / 2., size.y / 2.),
+                            sensor: Sensor,
+                            ..Default::default()
+                        },
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color,
+                                custom_size: Some(size),
+                                ..Default::default()
+                            },
+                            transform: Transform::from_xyz(
+                                (rect.left + rect.right + 1) as f32 * grid_size / 2.,
+                                (rect.bottom + rect.top + 1) as f32 * grid_size / 2.,
+                                TERRAIN_VOLUME_Z,
+                            ),
+                            ..Default::default()
+                        },
+                        TerrainVolume,
+                    ));
+
+                    if let Some(hazard) = hazard {
+                        volume.insert(*hazard);
+                    }
+                    if let Some(liquid) = liquid {
+                        volume.insert(*liquid);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Applies `TerrainEdit` events to the IntGrid of the level they land in.
+///
+/// Edited cells get the same bundles as cells spawned from the level file,
+/// and the level is flagged so `spawn_wall_collision` rebuilds its colliders.
+/// Tiles drawn over a removed cell are hidden,
+/// and cells added where the level file had none are drawn in their IntGrid value's color.
+#[allow(clippy::too_many_arguments)]
+pub fn edit_terrain(
+    mut commands: Commands,
+    mut terrain_edits: EventReader<TerrainEdit>,
+    level_query: Query<(Entity, &Transform, &Handle<LdtkLevel>, &Parent)>,
+    layer_query: Query<(&Parent, &TileStorage)>,
+    int_grid_cell_query: Query<&Parent, With<IntGridCell>>,
+    grid_coords_query: Query<(Entity, &GridCoords, &Parent, Option<&Sprite>)>,
+    world_query: Query<&Handle<LdtkAsset>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+) {
+    for terrain_edit in terrain_edits.iter() {
+        let position = match *terrain_edit {
+            TerrainEdit::Add { position, .. } | TerrainEdit::Remove { position } => position,
+        };
+
+        let edited_level = level_query
+            .iter()
+            .find_map(|(entity, transform, handle, parent)| {
+                let ldtk_level = ldtk_levels.get(handle)?;
+                let min = transform.translation.truncate();
+                let max = min
+                    + Vec2::new(
+                        ldtk_level.level.px_wid as f32,
+                        ldtk_level.level.px_hei as f32,
+                    );
+
+                (position.cmpge(min).all() && position.cmplt(max).all()).then_some((
+                    entity,
+                    min,
+                    ldtk_level,
+                    parent.get(),
+                ))
+            });
+
+        let (level_entity, level_translation, ldtk_level, world_entity) = match edited_level {
+            Some(edited_level) => edited_level,
+            None => {
+                warn!("Ignoring terrain edit outside of any loaded level: {terrain_edit:?}");
+                continue;
+            }
+        };
+
+        // IntGrid cells are children of their layer, which is a child of the level
+        let int_grid_layer = int_grid_cell_query
+            .iter()
+            .map(Parent::get)
+            .find(|layer| {
+                matches!(layer_query.get(*layer), Ok((level, _)) if level.get() == level_entity)
+            });
+
+        let layer_instance = ldtk_level
+            .level
+            .layer_instances
+            .iter()
+            .flatten()
+            .find(|layer_instance| !layer_instance.int_grid_csv.is_empty());
+
+        let (layer_entity, layer_instance) = match (int_grid_layer, layer_instance) {
+            (Some(layer_entity), Some(layer_instance)) => (layer_entity, layer_instance),
+            _ => {
+                warn!(
+                    "Ignoring terrain edit in level {} without IntGrid cells",
+                    ldtk_level.level.identifier
+                );
+                continue;
+            }
+        };
+
+        let grid_coords = translation_to_grid_coords(
+            position - level_translation,
+            IVec2::splat(layer_instance.grid_size),
+        );
+
+        let existing_cell = grid_coords_query
+            .iter()
+            .find(|(_, &coords, layer, _)| coords == grid_coords && layer.get() == layer_entity);
+
+        // Only the IntGrid layer's own tiles, like its auto-layer tiles, show the edited cell.
+        // Background and decoration layers are left as they are.
+        let tile_pos = TilePos::new(grid_coords.x as u32, grid_coords.y as u32);
+        let tile_entity = layer_query
+            .get(layer_entity)
+            .ok()
+            .and_then(|(_, tile_storage)| tile_storage.get(&tile_pos));
+
+        match *terrain_edit {
+            TerrainEdit::Remove { .. } => {
+                match existing_cell {
+                    // cells spawned by a previous edit only exist to be drawn
+                    Some((cell_entity, _, _, Some(_))) => {
+                        commands.entity(cell_entity).despawn_recursive();
+                    }
+                    Some((cell_entity, _, _, None)) => {
+                        commands
+                            .entity(cell_entity)
+                            .remove::<IntGridCell>()
+                            .remove::<WallBundle>()
+                            .remove::<LadderBundle>()
+                            .remove::<HazardBundle>()
+                            .remove::<LiquidBundle>();
                     }
+                    None => continue,
+                }
 
-                    plate_stack.push(row_plates);
+                if let Some(tile_entity) = tile_entity {
+                    commands.entity(tile_entity).insert(TileVisible(false));
                 }
+            }
+            TerrainEdit::Add { value, .. } => {
+                let int_grid_cell = IntGridCell { value };
 
-                // combine "plates" into rectangles across multiple rows
-                let mut wall_rects: Vec<Rect> = Vec::new();
-                let mut previous_rects: HashMap<Plate, Rect> = HashMap::new();
-
-                // an extra empty row so the algorithm "terminates" the rects that touch the top
-                // edge
-                plate_stack.push(Vec::new());
-
-                for (y, row) in plate_stack.iter().enumerate() {
-                    let mut current_rects: HashMap<Plate, Rect> = HashMap::new();
-                    for plate in row {
-                        if let Some(previous_rect) = previous_rects.remove(plate) {
-                            current_rects.insert(
-                                *plate,
-                                Rect {
-                                    top: previous_rect.top + 1,
-                                    ..previous_rect
-                                },
-                            );
-                        } else {
-                            current_rects.insert(
-                                *plate,
-                                Rect {
-                                    bottom: y as i32,
-                                    top: y as i32,
-                                    left: plate.left,
-                                    right: plate.right,
-                                },
-                            );
+                let mut cell = match existing_cell {
+                    Some((cell_entity, ..)) => {
+                        if let Some(tile_entity) = tile_entity {
+                            commands.entity(tile_entity).insert(TileVisible(true));
                         }
+
+                        let mut cell = commands.entity(cell_entity);
+                        cell.remove::<WallBundle>()
+                            .remove::<LadderBundle>()
+                            .remove::<HazardBundle>()
+                            .remove::<LiquidBundle>();
+                        cell
                     }
+                    None => {
+                        let color = world_query
+                            .get(world_entity)
+                            .ok()
+                            .and_then(|handle| ldtk_assets.get(handle))
+                            .and_then(|ldtk_asset| {
+                                ldtk_asset
+                                    .project
+                                    .defs
+                                    .layers
+                                    .iter()
+                                    .find(|layer| layer.uid == layer_instance.layer_def_uid)?
+                                    .int_grid_values
+                                    .iter()
+                                    .find(|int_grid_value| int_grid_value.value == value)
+                                    .map(|int_grid_value| int_grid_value.color)
+                            })
+                            .unwrap_or(Color::WHITE);
 
-                    // Any plates that weren't removed above have terminated
-                    wall_rects.append(&mut previous_rects.values().copied().collect());
-                    previous_rects = current_rects;
-                }
+                        let translation = grid_coords_to_translation(
+                            grid_coords,
+                            IVec2::splat(layer_instance.grid_size),
+                        );
 
-                commands.entity(level_entity).with_children(|level| {
-                    // Spawn colliders for every rectangle..
-                    // Making the collider a child of the level serves two purposes:
-                    // 1. Adjusts the transforms to be relative to the level for free
-                    // 2. the colliders will be despawned automatically when levels unload
-                    for wall_rect in wall_rects {
-                        level
-                            .spawn_empty()
-                            .insert(Collider::cuboid(
-                                (wall_rect.right as f32 - wall_rect.left as f32 + 1.)
-                                    * grid_size as f32
-                                    / 2.,
-                                (wall_rect.top as f32 - wall_rect.bottom as f32 + 1.)
-                                    * grid_size as f32
-                                    / 2.,
-                            ))
-                            .insert(RigidBody::Fixed)
-                            .insert(Friction::new(1.0))
-                            .insert(Transform::from_xyz(
-                                (wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32
-                                    / 2.,
-                                (wall_rect.bottom + wall_rect.top + 1) as f32 * grid_size as f32
-                                    / 2.,
-                                0.,
-                            ))
-                            .insert(GlobalTransform::default());
+                        let cell_entity = commands
+                            .spawn(SpriteBundle {
+                                sprite: Sprite {
+                                    color,
+                                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32)),
+                                    ..Default::default()
+                                },
+                                transform: Transform::from_translation(translation.extend(0.)),
+                                ..Default::default()
+                            })
+                            .insert(grid_coords)
+                            .id();
+                        commands.entity(layer_entity).add_child(cell_entity);
+                        commands.entity(cell_entity)
                     }
-                });
+                };
+
+                cell.insert(int_grid_cell);
+                insert_int_cell_bundle(&mut cell, int_grid_cell, layer_instance);
             }
-        });
+        }
+
+        commands.entity(level_entity).insert(RebuildWallCollision);
+    }
+}
+
+/// Digs out the terrain under the cursor with the left mouse button,
+/// and fills it back in with a wall using the right mouse button.
+///
+/// Clicks select entities instead while the debug overlay is open.
+pub fn dig_and_build(
+    mouse_input: Res<Input<MouseButton>>,
+    debug_overlay: Res<DebugOverlay>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut terrain_edits: EventWriter<TerrainEdit>,
+) {
+    let dig = mouse_input.just_pressed(MouseButton::Left);
+    let build = mouse_input.just_pressed(MouseButton::Right);
+
+    if debug_overlay.enabled || (!dig && !build) {
+        return;
+    }
+
+    let cursor_position = windows.get_primary().and_then(Window::cursor_position);
+
+    for (camera, camera_transform) in &camera_query {
+        if let Some(ray) =
+            cursor_position.and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        {
+            let position = ray.origin.truncate();
+
+            terrain_edits.send(if dig {
+                TerrainEdit::Remove { position }
+            } else {
+                TerrainEdit::Add {
+                    position,
+                    value: DIRT,
+                }
+            });
+        }
     }
 }
 
+/// Tracks the climbables each climber overlaps.
+///
+/// Runs in `TickStage::Last`, so it reads the collision events of the same tick's physics step.
 pub fn detect_climb_range(
     mut climbers: Query<&mut Climber>,
     climbables: Query<Entity, With<Climbable>>,
@@ -270,6 +925,78 @@ pub fn detect_climb_range(
     }
 }
 
+/// Keeps climbers on their ladder, and handles getting on and off at the top.
+///
+/// Runs after `movement`, overriding its horizontal velocity while climbing.
+#[allow(clippy::type_complexity)]
+pub fn climb_ladders(
+    actions: Res<ActionState>,
+    config: Res<PlayerMovementConfig>,
+    mut climbers: Query<
+        (
+            &mut Transform,
+            &GlobalTransform,
+            &Collider,
+            &mut Velocity,
+            &mut Climber,
+            &mut CollisionGroups,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
+    columns: Query<(&GlobalTransform, &LadderColumn)>,
+) {
+    for (mut transform, global_transform, collider, mut velocity, mut climber, mut groups) in
+        &mut climbers
+    {
+        let position = global_transform.translation().truncate();
+        let half_height = collider
+            .as_cuboid()
+            .map_or(0., |cuboid| cuboid.half_extents().y);
+        let feet = position.y - half_height;
+
+        let column = climber
+            .intersecting_climbables
+            .iter()
+            .filter_map(|entity| columns.get(*entity).ok())
+            .map(|(column_transform, column)| {
+                let center = column_transform.translation().truncate();
+                (center.x, center.y + column.top)
+            })
+            .min_by(|(a, _), (b, _)| (a - position.x).abs().total_cmp(&(b - position.x).abs()));
+
+        let mut passes_through_top = false;
+
+        if let Some((center_x, top)) = column {
+            let on_top = feet >= top - LADDER_GRAB_HEIGHT;
+
+            // Holding down on the top of a ladder starts climbing down
+            if !climber.climbing && on_top && actions.axis(ActionAxis::Climb) < 0. {
+                climber.climbing = true;
+            }
+
+            if climber.climbing {
+                velocity.linvel.x = (center_x - position.x) * config.ladder_snap_rate;
+
+                // Step off onto the floor at the top instead of climbing into the air
+                if velocity.linvel.y > 0. && feet >= top {
+                    climber.climbing = false;
+                    transform.translation.y += top - feet;
+                    velocity.linvel.y = 0.;
+                }
+            }
+
+            passes_through_top = climber.climbing || !on_top;
+        }
+
+        // Other systems own the rest of the filters, like `one_way_platforms`
+        let mut filters = groups.filters;
+        filters.set(LADDER_TOP_GROUP, !passes_through_top);
+        if groups.filters != filters {
+            groups.filters = filters;
+        }
+    }
+}
+
 pub fn ignore_gravity_if_climbing(
     mut query: Query<(&Climber, &mut GravityScale), Changed<Climber>>,
 ) {
@@ -282,109 +1009,379 @@ pub fn ignore_gravity_if_climbing(
     }
 }
 
-pub fn patrol(mut query: Query<(&mut Transform, &mut Velocity, &mut Patrol)>) {
-    for (mut transform, mut velocity, mut patrol) in &mut query {
+/// The slowest an easing patrol gets near its points, as a fraction of its speed.
+const PATROL_MIN_EASE: f32 = 0.25;
+
+#[allow(clippy::type_complexity)]
+pub fn patrol(
+    mut query: Query<(
+        &mut Transform,
+        &mut Velocity,
+        &mut Patrol,
+        Option<&EnemyAi>,
+        Option<&MovingPlatform>,
+    )>,
+) {
+    let delta = TICK_SECONDS;
+
+    for (mut transform, mut velocity, mut patrol, enemy_ai, platform) in &mut query {
         if patrol.points.len() <= 1 {
             continue;
         }
 
-        let mut new_velocity =
-            (patrol.points[patrol.index] - transform.translation.truncate()).normalize() * 75.;
+        if matches!(platform, Some(platform) if !platform.moves()) {
+            velocity.linvel = Vec2::ZERO;
+            continue;
+        }
 
-        if new_velocity.dot(velocity.linvel) < 0. {
-            if patrol.index == 0 {
-                patrol.forward = true;
-            } else if patrol.index == patrol.points.len() - 1 {
-                patrol.forward = false;
-            }
+        // enemy_ai takes over while the enemy is busy with the player
+        if matches!(enemy_ai, Some(enemy_ai) if enemy_ai.state != EnemyState::Patrol) {
+            continue;
+        }
+
+        if patrol.finished {
+            velocity.linvel = Vec2::ZERO;
+            continue;
+        }
 
-            transform.translation.x = patrol.points[patrol.index].x;
-            transform.translation.y = patrol.points[patrol.index].y;
+        if patrol.wait_timer > 0. {
+            patrol.wait_timer -= delta;
+            velocity.linvel = Vec2::ZERO;
+            continue;
+        }
 
-            if patrol.forward {
-                patrol.index += 1;
-            } else {
-                patrol.index -= 1;
-            }
+        let position = transform.translation.truncate();
+        let target = patrol.points[patrol.index];
+        let distance = position.distance(target);
+
+        let mut speed = patrol.speed;
+        if patrol.ease_distance > 0. {
+            // Slow down both when leaving a point and when approaching the next one
+            let closest_point = patrol
+                .points
+                .iter()
+                .map(|point| point.distance(position))
+                .fold(f32::INFINITY, f32::min);
+            speed *= (closest_point / patrol.ease_distance).clamp(PATROL_MIN_EASE, 1.);
+        }
+
+        // Arrive by distance, so a long frame can't carry the entity past the point
+        if distance <= speed * delta {
+            transform.translation.x = target.x;
+            transform.translation.y = target.y;
+            velocity.linvel = Vec2::ZERO;
 
-            new_velocity =
-                (patrol.points[patrol.index] - transform.translation.truncate()).normalize() * 75.;
+            let index = patrol.index;
+            patrol.wait_timer = patrol.wait_at(index);
+            patrol.resume_from(index);
+        } else {
+            velocity.linvel = (target - position) / distance * speed;
         }
+    }
+}
+
+/// Whether the straight line between two points is free of level geometry.
+fn line_of_sight(rapier_context: &RapierContext, from: Vec2, to: Vec2) -> bool {
+    let offset = to - from;
+    let distance = offset.length();
 
-        velocity.linvel = new_velocity;
+    if distance == 0. {
+        return true;
     }
+
+    rapier_context
+        .cast_ray(
+            from,
+            offset / distance,
+            distance,
+            true,
+            QueryFilter::only_fixed().exclude_sensors(),
+        )
+        .is_none()
 }
 
-const ASPECT_RATIO: f32 = 16. / 9.;
+/// How far ahead a chasing enemy checks for walls.
+const ENEMY_WALL_PROBE: f32 = 8.;
 
-pub fn camera_fit_inside_current_level(
-    mut camera_query: Query<
+pub fn enemy_ai(
+    rapier_context: Res<RapierContext>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    mut enemy_query: Query<
         (
-            &mut bevy::render::camera::OrthographicProjection,
             &mut Transform,
+            &GlobalTransform,
+            &mut Velocity,
+            &mut Patrol,
+            &mut EnemyAi,
         ),
-        Without<Player>,
+        With<Enemy>,
     >,
-    player_query: Query<&Transform, With<Player>>,
-    level_query: Query<
-        (&Transform, &Handle<LdtkLevel>),
-        (Without<OrthographicProjection>, Without<Player>),
-    >,
-    level_selection: Res<LevelSelection>,
-    ldtk_levels: Res<Assets<LdtkLevel>>,
 ) {
-    if let Ok(Transform {
-        translation: player_translation,
-        ..
-    }) = player_query.get_single()
-    {
-        let player_translation = *player_translation;
-
-        let (mut orthographic_projection, mut camera_transform) = camera_query.single_mut();
-
-        for (level_transform, level_handle) in &level_query {
-            if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
-                let level = &ldtk_level.level;
-                if level_selection.is_match(&0, level) {
-                    let level_ratio = level.px_wid as f32 / ldtk_level.level.px_hei as f32;
-
-                    orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
-                    orthographic_projection.bottom = 0.;
-                    orthographic_projection.left = 0.;
-                    if level_ratio > ASPECT_RATIO {
-                        // level is wider than the screen
-                        orthographic_projection.top = (level.px_hei as f32 / 9.).round() * 9.;
-                        orthographic_projection.right = orthographic_projection.top * ASPECT_RATIO;
-                        camera_transform.translation.x = (player_translation.x
-                            - level_transform.translation.x
-                            - orthographic_projection.right / 2.)
-                            .clamp(0., level.px_wid as f32 - orthographic_projection.right);
-                        camera_transform.translation.y = 0.;
-                    } else {
-                        // level is taller than the screen
-                        orthographic_projection.right = (level.px_wid as f32 / 16.).round() * 16.;
-                        orthographic_projection.top = orthographic_projection.right / ASPECT_RATIO;
-                        camera_transform.translation.y = (player_translation.y
-                            - level_transform.translation.y
-                            - orthographic_projection.top / 2.)
-                            .clamp(0., level.px_hei as f32 - orthographic_projection.top);
-                        camera_transform.translation.x = 0.;
+    let delta = TICK_SECONDS;
+    let player_position = player_query
+        .get_single()
+        .ok()
+        .map(|transform| transform.translation().truncate());
+
+    for (mut transform, global_transform, mut velocity, mut patrol, mut ai) in &mut enemy_query {
+        let position = global_transform.translation().truncate();
+
+        let distance_to_player = player_position.map(|player| player.distance(position));
+        let visible_player =
+            player_position.filter(|player| line_of_sight(&rapier_context, position, *player));
+        let spotted = matches!(distance_to_player, Some(d) if d <= ai.sight_range)
+            && visible_player.is_some();
+
+        if let Some(player) = visible_player {
+            if spotted || ai.state == EnemyState::Chase {
+                ai.last_seen_player = player;
+            }
+        }
+
+        match ai.state {
+            EnemyState::Patrol => {
+                if spotted {
+                    ai.state = EnemyState::Alert;
+                    ai.timer = ai.alert_time;
+                }
+            }
+            EnemyState::Alert => {
+                velocity.linvel = Vec2::ZERO;
+
+                if !spotted {
+                    ai.state = EnemyState::Return;
+                } else {
+                    ai.timer -= delta;
+                    if ai.timer <= 0. {
+                        ai.state = EnemyState::Chase;
+                        ai.timer = ai.give_up_time;
                     }
+                }
+            }
+            EnemyState::Chase => {
+                let in_range = matches!(distance_to_player, Some(d) if d <= ai.chase_range)
+                    && visible_player.is_some();
 
-                    camera_transform.translation.x += level_transform.translation.x;
-                    camera_transform.translation.y += level_transform.translation.y;
+                if in_range {
+                    ai.timer = ai.give_up_time;
+                } else {
+                    ai.timer -= delta;
+                    if ai.timer <= 0. {
+                        ai.state = EnemyState::Return;
+                    }
+                }
+
+                // Stay at the same height, mobs only chase along the ground they patrol
+                let offset = ai.last_seen_player.x - position.x;
+                let direction = Vec2::new(offset.signum(), 0.);
+                let blocked = rapier_context
+                    .cast_ray(
+                        position,
+                        direction,
+                        ENEMY_WALL_PROBE,
+                        true,
+                        QueryFilter::only_fixed().exclude_sensors(),
+                    )
+                    .is_some();
+
+                velocity.linvel = if blocked || offset.abs() < 1. {
+                    Vec2::ZERO
+                } else {
+                    direction * ai.chase_speed
+                };
+            }
+            EnemyState::Return => {
+                if spotted {
+                    ai.state = EnemyState::Alert;
+                    ai.timer = ai.alert_time;
+                    velocity.linvel = Vec2::ZERO;
+                    continue;
+                }
+
+                let index = patrol.nearest_point(transform.translation.truncate());
+                let offset = patrol.points[index] - transform.translation.truncate();
+
+                if offset.length() <= patrol.speed * delta {
+                    transform.translation.x = patrol.points[index].x;
+                    transform.translation.y = patrol.points[index].y;
+                    patrol.resume_from(index);
+
+                    ai.state = EnemyState::Patrol;
+                    velocity.linvel = Vec2::ZERO;
+                } else {
+                    velocity.linvel = offset.normalize() * patrol.speed;
                 }
             }
         }
     }
 }
 
+/// Fraction of the remaining distance to cover this frame when easing at `rate`,
+/// independent of the frame rate.
+fn smoothing_factor(rate: f32, delta: f32) -> f32 {
+    1. - (-rate * delta).exp()
+}
+
+/// Clamps the center of a view on one axis so it stays inside the level,
+/// centering the view if it's larger than the level.
+fn clamp_view_center(center: f32, level_min: f32, level_size: f32, view_size: f32) -> f32 {
+    if view_size >= level_size {
+        level_min + level_size / 2.
+    } else {
+        center.clamp(
+            level_min + view_size / 2.,
+            level_min + level_size - view_size / 2.,
+        )
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn camera_follow_player(
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    windows: Res<Windows>,
+    mut camera_query: Query<
+        (
+            &mut bevy::render::camera::OrthographicProjection,
+            &mut Transform,
+            &mut CameraController,
+        ),
+        Without<Player>,
+    >,
+    player_query: Query<(&Transform, &Velocity), With<Player>>,
+    level_query: Query<
+        (&Transform, &Handle<LdtkLevel>),
+        (Without<OrthographicProjection>, Without<Player>),
+    >,
+    level_selection: Res<LevelSelection>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+) {
+    let delta = time.delta_seconds();
+
+    let window_ratio = match windows.get_primary() {
+        Some(window) if window.width() > 0. && window.height() > 0. => {
+            window.width() / window.height()
+        }
+        _ => return,
+    };
+
+    let (player_transform, player_velocity) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let player_position = player_transform.translation.truncate();
+
+    let (mut orthographic_projection, mut camera_transform, mut controller) =
+        match camera_query.get_single_mut() {
+            Ok(camera) => camera,
+            Err(_) => return,
+        };
+
+    let selected_level = level_query
+        .iter()
+        .find_map(|(level_transform, level_handle)| {
+            ldtk_levels
+                .get(level_handle)
+                .filter(|ldtk_level| level_selection.is_match(&0, &ldtk_level.level))
+                .map(|ldtk_level| (level_transform, &ldtk_level.level))
+        });
+    let (level_transform, level) = match selected_level {
+        Some(selected_level) => selected_level,
+        None => return,
+    };
+    let level_min = level_transform.translation.truncate();
+    let level_size = Vec2::new(level.px_wid as f32, level.px_hei as f32);
+
+    // Fill the window with the level, cutting off whichever side doesn't fit the window's shape
+    let view_size = if level_size.x / level_size.y > window_ratio {
+        Vec2::new(level_size.y * window_ratio, level_size.y)
+    } else {
+        Vec2::new(level_size.x, level_size.x / window_ratio)
+    };
+
+    let current_center = camera_transform.translation.truncate();
+    let current_size = Vec2::new(
+        orthographic_projection.right - orthographic_projection.left,
+        orthographic_projection.top - orthographic_projection.bottom,
+    );
+
+    let first_level = controller.level_iid.is_none();
+    if controller.level_iid.as_ref() != Some(&level.iid) {
+        if first_level {
+            controller.focus = player_position;
+        } else {
+            controller.transition = Some(CameraTransition {
+                from_center: current_center,
+                from_size: current_size,
+                elapsed: 0.,
+            });
+        }
+        controller.level_iid = Some(level.iid.clone());
+    }
+
+    // Only follow the player once it leaves the dead zone around the focus
+    let offset = player_position - controller.focus;
+    controller.focus += offset - offset.clamp(-config.dead_zone, config.dead_zone);
+
+    if player_velocity.linvel.x.abs() > 1. {
+        let look_ahead = player_velocity.linvel.x.signum() * config.look_ahead;
+        controller.look_ahead += (look_ahead - controller.look_ahead)
+            * smoothing_factor(config.look_ahead_smoothing, delta);
+    }
+
+    let target = controller.focus + Vec2::new(controller.look_ahead, 0.);
+    let target_center = Vec2::new(
+        clamp_view_center(target.x, level_min.x, level_size.x, view_size.x),
+        clamp_view_center(target.y, level_min.y, level_size.y, view_size.y),
+    );
+
+    let mut transition_finished = false;
+    let (center, size) = match &mut controller.transition {
+        Some(transition) => {
+            transition.elapsed += delta;
+            let progress = (transition.elapsed / config.level_transition_time).min(1.);
+            transition_finished = progress >= 1.;
+
+            // smoothstep
+            let eased = progress * progress * (3. - 2. * progress);
+            (
+                transition.from_center.lerp(target_center, eased),
+                transition.from_size.lerp(view_size, eased),
+            )
+        }
+        None if first_level => (target_center, view_size),
+        None => (
+            current_center.lerp(target_center, smoothing_factor(config.smoothing, delta)),
+            view_size,
+        ),
+    };
+
+    if transition_finished {
+        controller.transition = None;
+    }
+
+    orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
+    orthographic_projection.left = -size.x / 2.;
+    orthographic_projection.right = size.x / 2.;
+    orthographic_projection.bottom = -size.y / 2.;
+    orthographic_projection.top = size.y / 2.;
+
+    camera_transform.translation.x = center.x;
+    camera_transform.translation.y = center.y;
+}
+
 pub fn update_level_selection(
     level_query: Query<(&Handle<LdtkLevel>, &Transform), Without<Player>>,
     player_query: Query<&Transform, With<Player>>,
     mut level_selection: ResMut<LevelSelection>,
     ldtk_levels: Res<Assets<LdtkLevel>>,
+    door_transition: Res<DoorTransition>,
 ) {
+    // Doors select the destination level themselves
+    if door_transition.is_active() {
+        return;
+    }
+
     for (level_handle, level_transform) in &level_query {
         if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
             let level_bounds = Rect {
@@ -430,6 +1427,7 @@ pub fn spawn_ground_sensor(
                     .insert(ActiveEvents::COLLISION_EVENTS)
                     .insert(detector_shape)
                     .insert(Sensor)
+                    .insert(CollisionGroups::default())
                     .insert(Transform::from_translation(sensor_translation))
                     .insert(GlobalTransform::default())
                     .insert(GroundSensor {
@@ -441,6 +1439,40 @@ pub fn spawn_ground_sensor(
     }
 }
 
+/// Gives ground sensors the collision groups of their owner,
+/// so they don't touch the one-way platforms or ladder tops their owner is passing through.
+pub fn sync_ground_sensor_groups(
+    mut ground_sensors: Query<(&mut GroundSensor, &mut CollisionGroups)>,
+    colliders: Query<Option<&CollisionGroups>, Without<GroundSensor>>,
+) {
+    for (mut sensor, mut groups) in &mut ground_sensors {
+        let owner_groups = match colliders.get(sensor.ground_detection_entity) {
+            Ok(Some(owner_groups)) => *owner_groups,
+            _ => continue,
+        };
+
+        if *groups == owner_groups {
+            continue;
+        }
+        *groups = owner_groups;
+
+        // Ground the new groups filter out stops counting right away,
+        // rather than whenever the physics step reports the contact as stopped
+        let interacts = |entity: &Entity| {
+            let other = match colliders.get(*entity) {
+                Ok(Some(other)) => *other,
+                _ => CollisionGroups::default(),
+            };
+            owner_groups.memberships.intersects(other.filters)
+                && other.memberships.intersects(owner_groups.filters)
+        };
+        if !sensor.intersecting_ground_entities.iter().all(interacts) {
+            sensor.intersecting_ground_entities.retain(interacts);
+        }
+    }
+}
+
+/// Runs in `TickStage::Last`, see `detect_climb_range`.
 pub fn ground_detection(
     mut ground_sensors: Query<&mut GroundSensor>,
     mut collisions: EventReader<CollisionEvent>,
@@ -459,15 +1491,14 @@ pub fn ground_detection(
                     }
                 }
             }
+            // The other collider may already be despawned, e.g. when wall colliders are rebuilt,
+            // so this can't check for collidables
             CollisionEvent::Stopped(e1, e2, _) => {
-                if collidables.contains(*e1) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e2) {
-                        sensor.intersecting_ground_entities.remove(e1);
-                    }
-                } else if collidables.contains(*e2) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e1) {
-                        sensor.intersecting_ground_entities.remove(e2);
-                    }
+                if let Ok(mut sensor) = ground_sensors.get_mut(*e2) {
+                    sensor.intersecting_ground_entities.remove(e1);
+                }
+                if let Ok(mut sensor) = ground_sensors.get_mut(*e1) {
+                    sensor.intersecting_ground_entities.remove(e2);
                 }
             }
         }
@@ -485,14 +1516,577 @@ pub fn update_on_ground(
     }
 }
 
+pub fn spawn_wall_sensors(
+    mut commands: Commands,
+    detect_walls_for: Query<(Entity, &Collider), Added<WallDetection>>,
+) {
+    for (entity, shape) in &detect_walls_for {
+        if let Some(cuboid) = shape.as_cuboid() {
+            let Vec2 {
+                x: half_extents_x,
+                y: half_extents_y,
+            } = cuboid.half_extents();
+
+            let detector_shape = Collider::cuboid(2., half_extents_y / 2.0);
+
+            commands.entity(entity).with_children(|builder| {
+                for side in [-1., 1.] {
+                    builder
+                        .spawn_empty()
+                        .insert(ActiveEvents::COLLISION_EVENTS)
+                        .insert(detector_shape.clone())
+                        .insert(Sensor)
+                        .insert(Transform::from_xyz(side * half_extents_x, 0., 0.))
+                        .insert(GlobalTransform::default())
+                        .insert(WallSensor {
+                            wall_detection_entity: entity,
+                            side,
+                            intersecting_wall_entities: HashSet::new(),
+                        });
+                }
+            });
+        }
+    }
+}
+
+/// Runs in `TickStage::Last`, see `detect_climb_range`.
+pub fn wall_detection(
+    mut wall_sensors: Query<&mut WallSensor>,
+    mut collisions: EventReader<CollisionEvent>,
+    walls: Query<With<WallCollider>>,
+) {
+    for collision_event in collisions.iter() {
+        match collision_event {
+            CollisionEvent::Started(e1, e2, _) => {
+                if walls.contains(*e1) {
+                    if let Ok(mut sensor) = wall_sensors.get_mut(*e2) {
+                        sensor.intersecting_wall_entities.insert(*e1);
+                    }
+                } else if walls.contains(*e2) {
+                    if let Ok(mut sensor) = wall_sensors.get_mut(*e1) {
+                        sensor.intersecting_wall_entities.insert(*e2);
+                    }
+                }
+            }
+            CollisionEvent::Stopped(e1, e2, _) => {
+                if let Ok(mut sensor) = wall_sensors.get_mut(*e2) {
+                    sensor.intersecting_wall_entities.remove(e1);
+                }
+                if let Ok(mut sensor) = wall_sensors.get_mut(*e1) {
+                    sensor.intersecting_wall_entities.remove(e2);
+                }
+            }
+        }
+    }
+}
+
+pub fn update_on_wall(
+    mut wall_detectors: Query<&mut WallDetection>,
+    wall_sensors: Query<&WallSensor, Changed<WallSensor>>,
+) {
+    for sensor in &wall_sensors {
+        if let Ok(mut wall_detection) = wall_detectors.get_mut(sensor.wall_detection_entity) {
+            let on_wall = !sensor.intersecting_wall_entities.is_empty();
+
+            if sensor.side < 0. {
+                wall_detection.on_wall_left = on_wall;
+            } else {
+                wall_detection.on_wall_right = on_wall;
+            }
+        }
+    }
+}
+
+/// Picks the closest lever, door or chest in reach when the interact action is pressed.
+///
+/// Levers and doors are in reach within a margin around their sprite,
+/// chests within a distance of their center.
+#[allow(clippy::type_complexity)]
+pub fn select_interact_target(
+    actions: Res<ActionState>,
+    mut interact_target: ResMut<InteractTarget>,
+    players: Query<&GlobalTransform, (With<Player>, Without<Dying>)>,
+    levers: Query<(Entity, &GlobalTransform, &Sprite), With<Lever>>,
+    doors: Query<(Entity, &GlobalTransform, &Sprite), With<Door>>,
+    chests: Query<(Entity, &GlobalTransform, &Items), With<Chest>>,
+) {
+    let player_position = match players.get_single() {
+        Ok(transform) if actions.just_pressed(Action::Interact) => {
+            transform.translation().truncate()
+        }
+        _ => {
+            if interact_target.0.is_some() {
+                interact_target.0 = None;
+            }
+            return;
+        }
+    };
+
+    let offset = |transform: &GlobalTransform| player_position - transform.translation().truncate();
+    let within_margin = |transform: &GlobalTransform, sprite: &Sprite, margin: f32| {
+        let half_size = sprite.custom_size.unwrap_or_default() / 2.;
+        let offset = offset(transform);
+        offset.x.abs() <= half_size.x + margin && offset.y.abs() <= half_size.y + margin
+    };
+
+    let levers = levers
+        .iter()
+        .filter(|(_, transform, sprite)| within_margin(transform, sprite, LEVER_INTERACT_MARGIN))
+        .map(|(entity, transform, _)| (entity, transform));
+    let doors = doors
+        .iter()
+        .filter(|(_, transform, sprite)| within_margin(transform, sprite, DOOR_INTERACT_MARGIN))
+        .map(|(entity, transform, _)| (entity, transform));
+    let chests = chests
+        .iter()
+        .filter(|(_, transform, items)| {
+            !items.is_empty() && offset(transform).length() <= CHEST_INTERACT_RANGE
+        })
+        .map(|(entity, transform, _)| (entity, transform));
+
+    interact_target.0 = levers
+        .chain(doors)
+        .chain(chests)
+        .map(|(entity, transform)| (entity, offset(transform).length()))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, _)| entity);
+}
+
 pub fn restart_level(
     mut commands: Commands,
     level_query: Query<Entity, With<Handle<LdtkLevel>>>,
-    input: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
+    mut run_timer: ResMut<RunTimer>,
 ) {
-    if input.just_pressed(KeyCode::R) {
+    if actions.just_pressed(Action::Restart) {
         for level_entity in &level_query {
             commands.entity(level_entity).insert(Respawn);
         }
+
+        // Restarting starts a new run once the player respawns
+        run_timer.reset();
+    }
+}
+
+/// How far above a mob's center the player's center needs to be to stomp it.
+const STOMP_HEIGHT: f32 = 10.;
+
+#[allow(clippy::type_complexity)]
+pub fn enemy_contact(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    config: Res<CombatConfig>,
+    mut player_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &mut Velocity,
+            &mut Health,
+            &mut MovementState,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
+    enemy_query: Query<(&GlobalTransform, &EntityIid), With<Enemy>>,
+    mut progress: ResMut<WorldProgress>,
+) {
+    for (player_entity, player_transform, mut velocity, mut health, mut state) in &mut player_query
+    {
+        for contact_pair in rapier_context.contacts_with(player_entity) {
+            if !contact_pair.has_any_active_contacts() {
+                continue;
+            }
+
+            let other = if contact_pair.collider1() == player_entity {
+                contact_pair.collider2()
+            } else {
+                contact_pair.collider1()
+            };
+
+            let (enemy_transform, enemy_iid) = match enemy_query.get(other) {
+                Ok(enemy) => enemy,
+                Err(_) => continue,
+            };
+
+            let offset =
+                (player_transform.translation() - enemy_transform.translation()).truncate();
+
+            if offset.y > STOMP_HEIGHT && velocity.linvel.y <= 0. {
+                commands.entity(other).despawn_recursive();
+                progress.defeated_mobs.insert(enemy_iid.0.clone());
+                velocity.linvel.y = config.stomp_bounce;
+                state.jumping = false;
+            } else if health.invincible_for <= 0. && health.current > 0 {
+                health.current -= config.contact_damage;
+                health.invincible_for = config.invincibility_time;
+
+                let direction = if offset.x < 0. { -1. } else { 1. };
+                velocity.linvel = Vec2::new(direction * config.knockback.x, config.knockback.y);
+                state.control_lock = config.knockback_control_lock;
+                state.jumping = false;
+            }
+        }
+
+        if health.current <= 0 {
+            commands.entity(player_entity).insert(Dying {
+                timer: Timer::from_seconds(config.death_time, TimerMode::Once),
+            });
+        }
+    }
+}
+
+/// Counts down invincibility, flickering the player's sprite while it lasts.
+pub fn update_invincibility(mut query: Query<(&mut Health, &mut Visibility)>) {
+    for (mut health, mut visibility) in &mut query {
+        if health.invincible_for <= 0. {
+            continue;
+        }
+
+        health.invincible_for = (health.invincible_for - TICK_SECONDS).max(0.);
+        visibility.is_visible =
+            health.invincible_for <= 0. || (health.invincible_for * 10.) as i32 % 2 == 0;
+    }
+}
+
+pub fn death_sequence(
+    mut commands: Commands,
+    respawn_point: Res<RespawnPoint>,
+    mut query: Query<(
+        Entity,
+        &mut Dying,
+        &mut Transform,
+        &mut Velocity,
+        &mut Health,
+        &mut MovementState,
+        &mut TextureAtlasSprite,
+    )>,
+) {
+    for (entity, mut dying, mut transform, mut velocity, mut health, mut state, mut sprite) in
+        &mut query
+    {
+        dying.timer.tick(TICK);
+
+        // Stop in place and fade to red
+        velocity.linvel.x = 0.;
+        let progress = dying.timer.percent();
+        sprite.color = Color::rgba(1., 1. - progress, 1. - progress, 1. - progress * 0.5);
+
+        if dying.timer.finished() {
+            if let Some(respawn_point) = respawn_point.position {
+                transform.translation.x = respawn_point.x;
+                transform.translation.y = respawn_point.y;
+            }
+
+            velocity.linvel = Vec2::ZERO;
+            health.current = health.max;
+            health.invincible_for = 0.;
+            *state = MovementState::default();
+            sprite.color = Color::WHITE;
+
+            commands.entity(entity).remove::<Dying>();
+        }
+    }
+}
+
+/// The area of overlap between two axis aligned boxes, given by their centers and half sizes.
+fn box_overlap(a: Vec2, a_half_size: Vec2, b: Vec2, b_half_size: Vec2) -> f32 {
+    let min = (a - a_half_size).max(b - b_half_size);
+    let max = (a + a_half_size).min(b + b_half_size);
+    let overlap = (max - min).max(Vec2::ZERO);
+    overlap.x * overlap.y
+}
+
+fn half_extents(collider: &Collider) -> Vec2 {
+    collider
+        .as_cuboid()
+        .map_or(Vec2::ZERO, |cuboid| cuboid.half_extents())
+}
+
+/// How far the player needs to overlap a hazard to be hurt by it, in pixels,
+/// so brushing against the edge of spikes is safe.
+const HAZARD_MARGIN: f32 = 2.;
+
+/// Hurts the player touching spikes, bouncing it up, and kills it in lava.
+#[allow(clippy::type_complexity)]
+pub fn hazard_contact(
+    mut commands: Commands,
+    config: Res<CombatConfig>,
+    mut player_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Collider,
+            &mut Velocity,
+            &mut Health,
+            &mut MovementState,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
+    hazards: Query<(&GlobalTransform, &Collider, &Hazard), With<TerrainVolume>>,
+) {
+    for (player_entity, player_transform, player_collider, mut velocity, mut health, mut state) in
+        &mut player_query
+    {
+        let player_position = player_transform.translation().truncate();
+        let player_half_size = (half_extents(player_collider) - HAZARD_MARGIN).max(Vec2::ZERO);
+
+        let mut touching = false;
+        let mut lethal = false;
+        for (hazard_transform, hazard_collider, hazard) in &hazards {
+            let overlap = box_overlap(
+                player_position,
+                player_half_size,
+                hazard_transform.translation().truncate(),
+                half_extents(hazard_collider),
+            );
+            if overlap > 0. {
+                touching = true;
+                lethal |= hazard.lethal;
+            }
+        }
+
+        if lethal {
+            health.current = 0;
+        } else if touching && health.invincible_for <= 0. && health.current > 0 {
+            health.current -= config.hazard_damage;
+            health.invincible_for = config.invincibility_time;
+            velocity.linvel.y = config.hazard_bounce;
+            state.control_lock = config.knockback_control_lock;
+            state.jumping = false;
+        }
+
+        if health.current <= 0 {
+            commands.entity(player_entity).insert(Dying {
+                timer: Timer::from_seconds(config.death_time, TimerMode::Once),
+            });
+        }
+    }
+}
+
+/// Measures how much of every swimmer is submerged in liquid volumes.
+pub fn detect_liquids(
+    mut swimmers: Query<(&GlobalTransform, &Collider, &mut Swimmer)>,
+    liquids: Query<(&GlobalTransform, &Collider, &Liquid), With<TerrainVolume>>,
+) {
+    for (transform, collider, mut swimmer) in &mut swimmers {
+        let position = transform.translation().truncate();
+        let half_size = half_extents(collider);
+        let area = half_size.x * half_size.y * 4.;
+
+        // Volumes don't overlap, so their shares of the swimmer add up
+        let mut submerged = 0.;
+        let mut deepest_overlap = 0.;
+        let mut liquid = None;
+        for (liquid_transform, liquid_collider, volume) in &liquids {
+            let overlap = box_overlap(
+                position,
+                half_size,
+                liquid_transform.translation().truncate(),
+                half_extents(liquid_collider),
+            );
+            if overlap <= 0. {
+                continue;
+            }
+
+            submerged += overlap;
+            if overlap > deepest_overlap {
+                deepest_overlap = overlap;
+                liquid = Some(volume.kind);
+            }
+        }
+
+        let submerged = if area > 0. {
+            (submerged / area).min(1.)
+        } else {
+            0.
+        };
+
+        if swimmer.submerged != submerged || swimmer.liquid != liquid {
+            swimmer.submerged = submerged;
+            swimmer.liquid = liquid;
+        }
+    }
+}
+
+/// Buoyancy, drag and reduced gravity for swimmers, scaled by how much of them is submerged.
+///
+/// Bodies less dense than the liquid float up, denser ones sink slowly.
+#[allow(clippy::type_complexity)]
+pub fn apply_liquids(
+    rapier_config: Res<RapierConfiguration>,
+    liquid_config: Res<LiquidConfig>,
+    mut swimmers: Query<(
+        &Swimmer,
+        &ColliderMassProperties,
+        &mut Velocity,
+        &mut GravityScale,
+        Option<&Climber>,
+    )>,
+) {
+    let delta = TICK_SECONDS;
+
+    for (swimmer, mass_properties, mut velocity, mut gravity_scale, climber) in &mut swimmers {
+        // Climbers' gravity is handled by `ignore_gravity_if_climbing`
+        if matches!(climber, Some(climber) if climber.climbing) {
+            continue;
+        }
+
+        let liquid = match swimmer.liquid {
+            Some(kind) => liquid_config.properties(kind),
+            None => {
+                if gravity_scale.0 != 1. {
+                    gravity_scale.0 = 1.;
+                }
+                continue;
+            }
+        };
+
+        let density = match mass_properties {
+            ColliderMassProperties::Density(density) => *density,
+            _ => 1.,
+        };
+
+        gravity_scale.0 = 1. - swimmer.submerged * (1. - liquid.gravity_scale);
+        velocity.linvel.y +=
+            -rapier_config.gravity.y * swimmer.submerged * liquid.density / density * delta;
+        velocity.linvel *= (1. - liquid.drag * swimmer.submerged * delta).max(0.);
+    }
+}
+
+/// Swimming controls for the player while it's deep enough in a liquid.
+///
+/// Runs after `movement`, slowing it down to the liquid's swim speed.
+/// Jumping swims up with a stroke, and the climb axis steers up and down.
+#[allow(clippy::type_complexity)]
+pub fn swim(
+    actions: Res<ActionState>,
+    liquid_config: Res<LiquidConfig>,
+    mut players: Query<
+        (&Swimmer, &Climber, &mut Velocity, &mut MovementState),
+        (With<Player>, Without<Dying>),
+    >,
+) {
+    for (swimmer, climber, mut velocity, mut state) in &mut players {
+        if climber.climbing {
+            continue;
+        }
+
+        let liquid = match swimmer.liquid {
+            Some(kind) if swimmer.submerged >= liquid_config.swim_depth => {
+                liquid_config.properties(kind)
+            }
+            _ => continue,
+        };
+
+        velocity.linvel.x = velocity
+            .linvel
+            .x
+            .clamp(-liquid.swim_speed, liquid.swim_speed);
+
+        let vertical = actions.axis(ActionAxis::Climb) * liquid.swim_speed;
+        if vertical > 0. {
+            velocity.linvel.y = velocity.linvel.y.max(vertical);
+        } else if vertical < 0. {
+            velocity.linvel.y = velocity.linvel.y.min(vertical);
+        }
+
+        if actions.just_pressed(Action::Jump) {
+            velocity.linvel.y = velocity.linvel.y.max(liquid.stroke_speed);
+            state.jump_buffer = 0.;
+        }
+
+        // Strokes aren't cut short like jumps
+        state.jumping = false;
+    }
+}
+
+/// How close the player needs to get to a checkpoint to activate it.
+const CHECKPOINT_RANGE: f32 = 16.;
+
+#[allow(clippy::type_complexity)]
+pub fn update_respawn_point(
+    mut respawn_point: ResMut<RespawnPoint>,
+    spawned_players: Query<&Transform, Added<Player>>,
+    player_query: Query<(&Transform, &GlobalTransform), (With<Player>, Without<Dying>)>,
+    mut checkpoint_query: Query<
+        (&GlobalTransform, &mut TextureAtlasSprite, &EntityIid),
+        With<Checkpoint>,
+    >,
+    mut progress: ResMut<WorldProgress>,
+) {
+    for transform in &spawned_players {
+        *respawn_point = RespawnPoint {
+            position: Some(transform.translation.truncate()),
+            checkpoint: None,
+        };
+    }
+
+    for (player_transform, player_global_transform) in &player_query {
+        // Checkpoints live in levels while the player is worldly,
+        // so positions are converted into the player's parent space
+        let parent_offset = player_global_transform.translation().truncate()
+            - player_transform.translation.truncate();
+
+        for (checkpoint_transform, _, checkpoint_iid) in &checkpoint_query {
+            let checkpoint_position = checkpoint_transform.translation().truncate();
+
+            if checkpoint_position.distance(player_global_transform.translation().truncate())
+                <= CHECKPOINT_RANGE
+            {
+                if respawn_point.checkpoint.as_ref() != Some(&checkpoint_iid.0) {
+                    *respawn_point = RespawnPoint {
+                        position: Some(checkpoint_position - parent_offset),
+                        checkpoint: Some(checkpoint_iid.0.clone()),
+                    };
+                }
+                if !progress.reached_checkpoints.contains(&checkpoint_iid.0) {
+                    progress
+                        .reached_checkpoints
+                        .insert(checkpoint_iid.0.clone());
+                }
+            }
+        }
+    }
+
+    // Light up the active checkpoint, and dimly the ones reached before, saved ones included
+    for (_, mut sprite, checkpoint_iid) in &mut checkpoint_query {
+        let color = if respawn_point.checkpoint.as_ref() == Some(&checkpoint_iid.0) {
+            Color::WHITE
+        } else if progress.reached_checkpoints.contains(&checkpoint_iid.0) {
+            Color::rgb(0.7, 0.7, 0.7)
+        } else {
+            Color::rgb(0.4, 0.4, 0.4)
+        };
+
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+/// Reapplies `WorldProgress` to chests, mobs and levers whenever their level spawns.
+#[allow(clippy::type_complexity)]
+pub fn apply_world_progress(
+    mut commands: Commands,
+    progress: Res<WorldProgress>,
+    mut chest_query: Query<(&EntityIid, &mut Items), (Added<EntityIid>, With<Chest>)>,
+    mob_query: Query<(Entity, &EntityIid), (Added<EntityIid>, With<Enemy>)>,
+    mut lever_query: Query<(&EntityIid, &mut Switch), (Added<EntityIid>, With<Lever>)>,
+) {
+    for (iid, mut items) in &mut chest_query {
+        if progress.opened_chests.contains(&iid.0) {
+            *items = Items::default();
+        }
+    }
+
+    for (entity, iid) in &mob_query {
+        if progress.defeated_mobs.contains(&iid.0) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    for (iid, mut switch) in &mut lever_query {
+        if progress.toggled_levers.contains(&iid.0) {
+            switch.on = !switch.on;
+        }
     }
 }
@@ -0,0 +1,149 @@
+//! Runs the platformer and its physics on a fixed timestep,
+//! so the same actions every tick always play out the same way.
+//!
+//! Ticks run in the `FixedUpdate` schedule after `CoreStage::Update`,
+//! as many times per frame as needed to keep up with `TICK_RATE`.
+//! While menus pause physics no ticks run at all, and the paused time is never caught up on.
+
+use crate::input::*;
+use bevy::{
+    ecs::schedule::{IntoSystemDescriptor, ShouldRun},
+    prelude::*,
+};
+use bevy_rapier2d::prelude::*;
+
+use std::{mem, time::Duration};
+
+/// Ticks per second.
+pub const TICK_RATE: u64 = 60;
+
+pub const TICK: Duration = Duration::from_nanos(1_000_000_000 / TICK_RATE);
+
+/// Length of a tick in seconds, what tick systems use instead of `Time::delta_seconds`.
+pub const TICK_SECONDS: f32 = 1. / TICK_RATE as f32;
+
+/// Label of the schedule running the tick stages.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct FixedUpdate;
+
+/// The stages of a tick, rapier's stages run between `Update` and `Last`.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub enum TickStage {
+    /// Sets up the actions of the tick
+    First,
+    Update,
+    /// Runs after the physics step, once positions are up to date.
+    /// Collision events are read here, before they can be cleared between ticks
+    Last,
+}
+
+/// Time towards the next tick, kept by `run_ticks`.
+#[derive(Clone, Debug, Default)]
+pub struct TickClock {
+    accumulated: Duration,
+    /// Set while the ticks owed for the frame are running
+    looping: bool,
+}
+
+/// Runs as many ticks as the frame's time makes up for, none while physics is paused.
+pub fn run_ticks(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    mut clock: Local<TickClock>,
+) -> ShouldRun {
+    if !clock.looping {
+        if !rapier_config.physics_pipeline_active {
+            return ShouldRun::No;
+        }
+        clock.accumulated += time.delta();
+    }
+
+    if clock.accumulated >= TICK {
+        clock.accumulated -= TICK;
+        clock.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
+
+/// The schedule for `FixedUpdate`, expects rapier to be added without its default system setup.
+pub fn tick_schedule() -> Schedule {
+    let physics_stage = |stage: PhysicsStages| {
+        SystemStage::parallel()
+            .with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(stage))
+    };
+
+    Schedule::default()
+        .with_run_criteria(run_ticks)
+        .with_stage(TickStage::First, SystemStage::parallel())
+        .with_stage(TickStage::Update, SystemStage::parallel())
+        .with_stage(
+            PhysicsStages::SyncBackend,
+            physics_stage(PhysicsStages::SyncBackend),
+        )
+        .with_stage(
+            PhysicsStages::StepSimulation,
+            physics_stage(PhysicsStages::StepSimulation),
+        )
+        .with_stage(
+            PhysicsStages::Writeback,
+            physics_stage(PhysicsStages::Writeback),
+        )
+        .with_stage(TickStage::Last, SystemStage::parallel())
+}
+
+/// Adds systems to the stages of `FixedUpdate`.
+pub trait AddTickSystem {
+    fn add_tick_system_to_stage<Params>(
+        &mut self,
+        stage: TickStage,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+
+    /// Adds a system to `TickStage::Update`.
+    fn add_tick_system<Params>(&mut self, system: impl IntoSystemDescriptor<Params>) -> &mut Self {
+        self.add_tick_system_to_stage(TickStage::Update, system)
+    }
+}
+
+impl AddTickSystem for App {
+    fn add_tick_system_to_stage<Params>(
+        &mut self,
+        stage: TickStage,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.stage(FixedUpdate, |schedule: &mut Schedule| {
+            schedule.add_system_to_stage(stage, system)
+        })
+    }
+}
+
+/// The actions of the ticks, kept apart from the actions of the frames.
+///
+/// Frames and ticks don't line up, so the actions of every frame are buffered until the next tick.
+/// `ActionState` is swapped with the tick's actions while the tick runs,
+/// so tick systems read their actions like every other system.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct TickActions {
+    /// Built up from the frames since the last tick
+    next: ActionState,
+    /// The frame's actions, while a tick runs
+    frame: ActionState,
+}
+
+/// Runs in `CoreStage::PreUpdate` after the menus, which hide the actions they use from ticks.
+pub fn buffer_tick_actions(actions: Res<ActionState>, mut tick_actions: ResMut<TickActions>) {
+    tick_actions.next.accumulate(&actions);
+}
+
+pub fn begin_tick(mut actions: ResMut<ActionState>, mut tick_actions: ResMut<TickActions>) {
+    let tick = tick_actions.next.clone();
+    tick_actions.next.clear_just_pressed();
+    tick_actions.frame = mem::replace(&mut *actions, tick);
+}
+
+pub fn end_tick(mut actions: ResMut<ActionState>, mut tick_actions: ResMut<TickActions>) {
+    *actions = mem::take(&mut tick_actions.frame);
+}
@@ -0,0 +1,276 @@
+//! Checks LDtk levels for data the game can't use.
+//!
+//! The game logs problems when a level loads and skips the offending entities,
+//! `src/bin/validate_ldtk.rs` reports them for whole projects without starting the game.
+
+use crate::{
+    components::EntityIid,
+    ldtk_fields::{EntityInstanceFields, FieldError, LdtkFields},
+    registry::{ENTITIES, INT_CELLS},
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{
+    ldtk::{LdtkJson, Level, Type},
+    prelude::*,
+};
+use thiserror::Error;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("level {level}: {entity} entity {iid}: {source}")]
+    InvalidField {
+        level: String,
+        entity: String,
+        iid: String,
+        source: FieldError,
+    },
+    #[error("level {level}: {entity} entity {iid}: point {point} of field `{field}` is outside of layer {layer}")]
+    PointOutOfBounds {
+        level: String,
+        entity: String,
+        iid: String,
+        field: String,
+        layer: String,
+        point: IVec2,
+    },
+    #[error("level {level}: layer {layer} has {count} cells with the unregistered IntGrid value {value}")]
+    UnregisteredIntGridValue {
+        level: String,
+        layer: String,
+        value: i32,
+        count: usize,
+    },
+    #[error("level {level} has no layer data")]
+    MissingLayers { level: String },
+}
+
+impl ValidationError {
+    /// The iid of the entity that should be skipped because of this error, if any.
+    pub fn entity_iid(&self) -> Option<&str> {
+        match self {
+            ValidationError::InvalidField { iid, .. }
+            | ValidationError::PointOutOfBounds { iid, .. } => Some(iid),
+            _ => None,
+        }
+    }
+}
+
+/// Checks an entity's fields the same way its bundle reads them.
+pub type EntityCheck = fn(&EntityInstance) -> Result<(), FieldError>;
+
+pub fn check_fields<T: LdtkFields>(entity_instance: &EntityInstance) -> Result<(), FieldError> {
+    T::from_ldtk_fields(entity_instance).map(|_| ())
+}
+
+pub fn check_patrol(entity_instance: &EntityInstance) -> Result<(), FieldError> {
+    entity_instance
+        .required_field::<Vec<IVec2>>("patrol")
+        .map(|_| ())
+}
+
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ValidationRules {
+    /// IntGrid values that have a bundle registered.
+    pub int_grid_values: HashSet<i32>,
+    /// Checks for the entities with a bundle registered, by identifier.
+    pub entities: HashMap<String, Vec<EntityCheck>>,
+}
+
+impl ValidationRules {
+    /// The rules for the bundles in `registry`, which `GamePlugin` registers.
+    pub fn game() -> Self {
+        ValidationRules {
+            int_grid_values: INT_CELLS.iter().map(|int_cell| int_cell.value).collect(),
+            entities: ENTITIES
+                .iter()
+                .map(|entity| (entity.identifier.to_string(), entity.checks.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+/// Returns every problem in a level, which needs to have its layers loaded.
+pub fn validate_level(level: &Level, rules: &ValidationRules) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    let layer_instances = match &level.layer_instances {
+        Some(layer_instances) => layer_instances,
+        None => {
+            errors.push(ValidationError::MissingLayers {
+                level: level.identifier.clone(),
+            });
+            return errors;
+        }
+    };
+
+    for layer_instance in layer_instances {
+        match layer_instance.layer_instance_type {
+            Type::IntGrid => {
+                let mut counts: HashMap<i32, usize> = HashMap::new();
+                for value in &layer_instance.int_grid_csv {
+                    if *value != 0 && !rules.int_grid_values.contains(value) {
+                        *counts.entry(*value).or_default() += 1;
+                    }
+                }
+
+                let mut counts: Vec<_> = counts.into_iter().collect();
+                counts.sort();
+                for (value, count) in counts {
+                    errors.push(ValidationError::UnregisteredIntGridValue {
+                        level: level.identifier.clone(),
+                        layer: layer_instance.identifier.clone(),
+                        value,
+                        count,
+                    });
+                }
+            }
+            Type::Entities => {
+                for entity_instance in &layer_instance.entity_instances {
+                    validate_entity(level, layer_instance, entity_instance, rules, &mut errors);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    errors
+}
+
+fn validate_entity(
+    level: &Level,
+    layer_instance: &LayerInstance,
+    entity_instance: &EntityInstance,
+    rules: &ValidationRules,
+    errors: &mut Vec<ValidationError>,
+) {
+    for check in rules
+        .entities
+        .get(&entity_instance.identifier)
+        .into_iter()
+        .flatten()
+    {
+        if let Err(source) = check(entity_instance) {
+            errors.push(ValidationError::InvalidField {
+                level: level.identifier.clone(),
+                entity: entity_instance.identifier.clone(),
+                iid: entity_instance.iid.clone(),
+                source,
+            });
+        }
+    }
+
+    // Points are stored in grid coordinates of the entity's layer
+    let bounds = IVec2::new(layer_instance.c_wid, layer_instance.c_hei);
+    for field_instance in &entity_instance.field_instances {
+        let points: Vec<IVec2> = match &field_instance.value {
+            FieldValue::Point(point) => point.iter().copied().collect(),
+            FieldValue::Points(points) => points.iter().flatten().copied().collect(),
+            _ => continue,
+        };
+
+        for point in points {
+            if point.cmplt(IVec2::ZERO).any() || point.cmpge(bounds).any() {
+                errors.push(ValidationError::PointOutOfBounds {
+                    level: level.identifier.clone(),
+                    entity: entity_instance.identifier.clone(),
+                    iid: entity_instance.iid.clone(),
+                    field: field_instance.identifier.clone(),
+                    layer: layer_instance.identifier.clone(),
+                    point,
+                });
+            }
+        }
+    }
+}
+
+/// Loads a `.ldtk` project, including external level files, and validates all of its levels.
+pub fn validate_project_file(
+    path: &Path,
+    rules: &ValidationRules,
+) -> anyhow::Result<Vec<ValidationError>> {
+    let project: LdtkJson = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    let levels = project
+        .levels
+        .iter()
+        .chain(project.worlds.iter().flat_map(|world| world.levels.iter()));
+
+    let mut errors = Vec::new();
+    for level in levels {
+        match (&level.layer_instances, &level.external_rel_path) {
+            (None, Some(external_rel_path)) => {
+                let external_path = path
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(external_rel_path);
+                let external_level: Level =
+                    serde_json::from_str(&fs::read_to_string(&external_path)?)?;
+                errors.extend(validate_level(&external_level, rules));
+            }
+            _ => errors.extend(validate_level(level, rules)),
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Iids of the entities `validate_levels` found problems with, by level iid.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct InvalidEntities(HashMap<String, HashSet<String>>);
+
+impl InvalidEntities {
+    pub fn contains(&self, entity_iid: &str) -> bool {
+        self.0.values().any(|iids| iids.contains(entity_iid))
+    }
+}
+
+/// Logs the problems of every level as it's loaded or reloaded.
+pub fn validate_levels(
+    mut level_events: EventReader<AssetEvent<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+    rules: Res<ValidationRules>,
+    mut invalid_entities: ResMut<InvalidEntities>,
+) {
+    for level_event in level_events.iter() {
+        let handle = match level_event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        let level = match ldtk_levels.get(handle) {
+            Some(ldtk_level) => &ldtk_level.level,
+            None => continue,
+        };
+
+        let mut iids = HashSet::new();
+        for error in validate_level(level, &rules) {
+            error!("{error}");
+            if let Some(iid) = error.entity_iid() {
+                iids.insert(iid.to_string());
+            }
+        }
+
+        invalid_entities.0.insert(level.iid.clone(), iids);
+    }
+}
+
+/// Despawns entities with invalid data as they spawn, instead of letting them misbehave.
+///
+/// Every validated entity's bundle has an `EntityIid` for this.
+pub fn skip_invalid_entities(
+    mut commands: Commands,
+    invalid_entities: Res<InvalidEntities>,
+    new_entities: Query<(Entity, &EntityIid), Added<EntityIid>>,
+) {
+    for (entity, iid) in &new_entities {
+        if invalid_entities.contains(&iid.0) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
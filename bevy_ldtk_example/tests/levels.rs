@@ -0,0 +1,320 @@
+//! Runs the game without a window against the bundled sample project,
+//! one tick per frame, and checks the levels play out as expected.
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ButtonState},
+    log::LogPlugin,
+    prelude::*,
+    render::settings::WgpuSettings,
+    time::TimeUpdateStrategy,
+    winit::WinitPlugin,
+};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use bevy_ldtk_example::{
+    components::*,
+    game::GamePlugin,
+    level_select::{CurrentProject, LaunchOptions},
+    save::SaveDirectory,
+    tick::TICK,
+};
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Real time allowed for whatever a test waits on, the levels load in the background.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Frames to run after the player spawns for the neighbouring levels to finish spawning.
+const SETTLE_FRAMES: usize = 60;
+
+/// Merged wall colliders of each level in the sample project around the first level.
+const EXPECTED_WALL_COLLIDERS: [(&str, usize); 3] = [
+    ("Your_typical_2D_platformer", 24),
+    ("Top", 17),
+    ("Bottom", 5),
+];
+
+/// Removes the save directory of a test when its app is dropped, even if the test fails.
+#[derive(Resource)]
+struct TempSaveDirectory(PathBuf);
+
+impl Drop for TempSaveDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A fresh directory for the saves of a test, so tests don't overwrite the real saves
+/// or each other's.
+fn save_directory() -> (SaveDirectory, TempSaveDirectory) {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let path = env::temp_dir().join(format!(
+        "bevy_ldtk_example_tests_{}_{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    // Left over from an earlier run with the same process id
+    let _ = fs::remove_dir_all(&path);
+    (SaveDirectory(path.clone()), TempSaveDirectory(path))
+}
+
+/// The game in the default project's first level, without a window, renderer or logging.
+fn headless_app() -> App {
+    let start = LaunchOptions::default()
+        .start()
+        .expect("the default project should load");
+
+    let (save_directory, temp_save_directory) = save_directory();
+
+    let mut app = App::new();
+    app.insert_resource(WgpuSettings {
+        backends: None,
+        ..Default::default()
+    })
+    .add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                add_primary_window: false,
+                exit_on_all_closed: false,
+                ..Default::default()
+            })
+            .disable::<WinitPlugin>()
+            // Tests share the global tracing subscriber
+            .disable::<LogPlugin>(),
+    )
+    .insert_resource(save_directory)
+    .insert_resource(temp_save_directory)
+    .add_plugin(GamePlugin)
+    .insert_resource(CurrentProject(start.project))
+    .insert_resource(start.level_selection)
+    .insert_resource(start.transition)
+    // A tick every frame, however long the frames really take
+    .insert_resource(TimeUpdateStrategy::ManualDuration(TICK));
+    app
+}
+
+/// Runs frames until `condition` holds, failing the test once `TIMEOUT` is up.
+fn run_until(app: &mut App, waiting_for: &str, mut condition: impl FnMut(&mut World) -> bool) {
+    let started = Instant::now();
+    while !condition(&mut app.world) {
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "timed out waiting for {waiting_for}"
+        );
+        app.update();
+    }
+}
+
+fn run_frames(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.update();
+    }
+}
+
+fn player(world: &mut World) -> Option<Entity> {
+    world
+        .query_filtered::<Entity, With<Player>>()
+        .iter(world)
+        .next()
+}
+
+/// A headless app with the levels spawned around the player.
+fn spawned_app() -> App {
+    let mut app = headless_app();
+    run_until(&mut app, "the player to spawn", |world| {
+        player(world).is_some()
+    });
+    run_frames(&mut app, SETTLE_FRAMES);
+    app
+}
+
+fn send_key(app: &mut App, key_code: KeyCode, state: ButtonState) {
+    app.world.send_event(KeyboardInput {
+        scan_code: 0,
+        key_code: Some(key_code),
+        state,
+    });
+}
+
+/// Presses and releases a key over two frames.
+fn tap_key(app: &mut App, key_code: KeyCode) {
+    send_key(app, key_code, ButtonState::Pressed);
+    app.update();
+    send_key(app, key_code, ButtonState::Released);
+    app.update();
+}
+
+/// Moves a rigid body to `position` in world space, leaving it at rest.
+fn teleport(world: &mut World, entity: Entity, position: Vec2) {
+    let parent_transform = world
+        .get::<Parent>(entity)
+        .and_then(|parent| world.get::<GlobalTransform>(parent.get()))
+        .copied()
+        .unwrap_or_default();
+
+    let mut entity_mut = world.entity_mut(entity);
+    let z = entity_mut.get::<Transform>().unwrap().translation.z;
+    let local = parent_transform
+        .compute_matrix()
+        .inverse()
+        .transform_point3(position.extend(0.));
+
+    entity_mut.get_mut::<Transform>().unwrap().translation = local.truncate().extend(z);
+    // Rapier picks up changes to the global transform, which only propagates after the tick
+    *entity_mut.get_mut::<GlobalTransform>().unwrap() =
+        GlobalTransform::from_translation(position.extend(z));
+    entity_mut.get_mut::<Velocity>().unwrap().linvel = Vec2::ZERO;
+}
+
+/// Merged wall colliders of every spawned level, by level identifier.
+fn wall_colliders_per_level(world: &mut World) -> HashMap<String, usize> {
+    let level_identifiers: HashMap<Entity, String> = world
+        .query::<(Entity, &Handle<LdtkLevel>)>()
+        .iter(world)
+        .filter_map(|(entity, handle)| {
+            let ldtk_level = world.resource::<Assets<LdtkLevel>>().get(handle)?;
+            Some((entity, ldtk_level.level.identifier.clone()))
+        })
+        .collect();
+
+    let mut counts = HashMap::new();
+    for parent in world
+        .query_filtered::<&Parent, With<WallCollider>>()
+        .iter(world)
+    {
+        if let Some(identifier) = level_identifiers.get(&parent.get()) {
+            *counts.entry(identifier.clone()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// LDtk entities belonging to a level, unlike `Worldly` ones such as the player.
+fn level_entities(world: &mut World) -> HashSet<Entity> {
+    world
+        .query_filtered::<Entity, (With<EntityInstance>, Without<Worldly>)>()
+        .iter(world)
+        .collect()
+}
+
+#[test]
+fn player_spawns() {
+    let mut app = spawned_app();
+
+    let players: Vec<&EntityInstance> = app
+        .world
+        .query_filtered::<&EntityInstance, With<Player>>()
+        .iter(&app.world)
+        .collect();
+
+    assert_eq!(players.len(), 1, "expected a single player");
+    assert_eq!(players[0].identifier, "Player");
+}
+
+#[test]
+fn wall_colliders_are_stable() {
+    let mut app = spawned_app();
+
+    let walls = wall_colliders_per_level(&mut app.world);
+    let expected: HashMap<String, usize> = EXPECTED_WALL_COLLIDERS
+        .iter()
+        .map(|(identifier, count)| (identifier.to_string(), *count))
+        .collect();
+    assert_eq!(walls, expected);
+
+    run_frames(&mut app, SETTLE_FRAMES);
+    assert_eq!(
+        wall_colliders_per_level(&mut app.world),
+        walls,
+        "wall colliders changed while nothing happened"
+    );
+
+    // Respawned levels merge their walls the same way
+    let entities = level_entities(&mut app.world);
+    tap_key(&mut app, KeyCode::R);
+    run_until(&mut app, "the levels to respawn", |world| {
+        let respawned = level_entities(world);
+        respawned.len() == entities.len() && respawned.is_disjoint(&entities)
+    });
+    run_frames(&mut app, SETTLE_FRAMES);
+    assert_eq!(wall_colliders_per_level(&mut app.world), walls);
+}
+
+#[test]
+fn player_lands() {
+    let mut app = spawned_app();
+
+    run_until(&mut app, "the player to land", |world| {
+        let player = player(world).unwrap();
+        world.get::<GroundDetection>(player).unwrap().on_ground
+    });
+
+    // And stays on the ground
+    run_frames(&mut app, SETTLE_FRAMES);
+    let player = player(&mut app.world).unwrap();
+    assert!(app.world.get::<GroundDetection>(player).unwrap().on_ground);
+}
+
+#[test]
+fn climbing_ignores_gravity() {
+    let mut app = spawned_app();
+    run_until(&mut app, "the player to land", |world| {
+        let player = player(world).unwrap();
+        world.get::<GroundDetection>(player).unwrap().on_ground
+    });
+
+    let ladder = app
+        .world
+        .query_filtered::<&GlobalTransform, With<LadderColumn>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation().truncate())
+        .next()
+        .expect("the levels should have a ladder");
+
+    let player = player(&mut app.world).unwrap();
+    teleport(&mut app.world, player, ladder);
+    run_until(&mut app, "the player to reach the ladder", |world| {
+        !world
+            .get::<Climber>(player)
+            .unwrap()
+            .intersecting_climbables
+            .is_empty()
+    });
+
+    send_key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_until(&mut app, "the player to climb", |world| {
+        world.get::<Climber>(player).unwrap().climbing
+            && world.get::<GravityScale>(player).unwrap().0 == 0.
+    });
+}
+
+#[test]
+fn restart_respawns_level_entities() {
+    let mut app = spawned_app();
+
+    let before = level_entities(&mut app.world);
+    assert!(!before.is_empty(), "the levels have no entities");
+
+    tap_key(&mut app, KeyCode::R);
+    run_until(&mut app, "the level entities to respawn", |world| {
+        let after = level_entities(world);
+        after.len() == before.len() && after.is_disjoint(&before)
+    });
+
+    for entity in &before {
+        assert!(
+            app.world.get_entity(*entity).is_none(),
+            "{entity:?} survived the restart"
+        );
+    }
+    assert!(player(&mut app.world).is_some());
+}